@@ -0,0 +1,195 @@
+//! Compares the memory and time taken by a `SynapseGroup` against storing the
+//! same synapses in a dense matrix, and times `propagate` with spiking and
+//! continuous presynaptic voltages. Run with `cargo bench`.
+
+extern crate brian_rs;
+extern crate brian_rs_macros;
+extern crate dimensioned as dim;
+
+use brian_rs::synapses::{Connection, SynapseGroup, Synaptic};
+use dim::si;
+use std::time::Instant;
+
+brian_rs_macros::define_synapse! {
+    Stdp<si::Volt<f64>, si::Second<f64>>:
+    params {
+        tau_pre: f64, tau_post: f64, w: si::Unitless<f64>, activation_bump: f64
+    }
+    initialize {
+        a_pre: si::Unitless<f64> = 0.0 * si::S / si::S;
+        a_post: si::Unitless<f64> = 0.0 * si::S / si::S
+    }
+    time_step {
+        a_pre @ = - (self.a_pre / self.tau_pre) / si::S;
+        a_post @ = - (self.a_post / self.tau_post) / si::S
+    }
+    weight_getter { *self.w }
+    on_pre {
+        self.a_pre += self.activation_bump;
+        self.w += self.a_post
+    }
+    on_post {
+        self.a_post += self.activation_bump;
+        self.w += self.a_pre
+    }
+}
+
+const SYNAPSES_PER_NEURON: usize = 10;
+const STEPS: usize = 200;
+
+fn new_synapse() -> Stdp {
+    Stdp::new(0.02, 0.02, 0.5 * si::S / si::S, 0.01)
+}
+
+/// A fixed, scattered set of targets for each presynaptic neuron.
+fn targets(pre: usize, num_neurons: usize) -> impl Iterator<Item = usize> {
+    (0..SYNAPSES_PER_NEURON).map(move |k| (pre * 7919 + k * 104_729) % num_neurons)
+}
+
+/// Roughly 1% of the neurons spike on each step.
+fn spikes(step: usize, num_neurons: usize) -> Vec<bool> {
+    (0..num_neurons)
+        .map(|i| (i * 31 + step * 17).is_multiple_of(100))
+        .collect()
+}
+
+struct Dense {
+    synapses: Vec<Vec<Option<Stdp>>>,
+}
+
+impl Dense {
+    fn new(num_neurons: usize) -> Self {
+        let mut synapses: Vec<Vec<Option<Stdp>>> = (0..num_neurons)
+            .map(|_| (0..num_neurons).map(|_| None).collect())
+            .collect();
+        for (pre, row) in synapses.iter_mut().enumerate() {
+            for post in targets(pre, num_neurons) {
+                row[post] = Some(new_synapse());
+            }
+        }
+        Dense { synapses }
+    }
+
+    fn memory_usage(num_neurons: usize) -> usize {
+        num_neurons * num_neurons * std::mem::size_of::<Option<Stdp>>()
+            + num_neurons * std::mem::size_of::<Vec<Option<Stdp>>>()
+    }
+
+    fn step(&mut self, pre_spikes: &[bool], post_spikes: &[bool], dt: si::Second<f64>) {
+        for (pre, row) in self.synapses.iter_mut().enumerate() {
+            for (post, slot) in row.iter_mut().enumerate() {
+                if let Some(synapse) = slot {
+                    if pre_spikes[pre] {
+                        synapse.on_pre(0.5 * si::V);
+                    }
+                    if post_spikes[post] {
+                        synapse.on_post(0.5 * si::V);
+                    }
+                    synapse.advance_once(dt);
+                }
+            }
+        }
+    }
+}
+
+fn sparse(num_neurons: usize) -> SynapseGroup<Stdp, si::Volt<f64>, si::Second<f64>> {
+    let connections = (0..num_neurons)
+        .flat_map(|pre| targets(pre, num_neurons).map(move |post| (pre, post)))
+        .map(|(pre, post)| Connection::new(pre, post, new_synapse()))
+        .collect();
+    SynapseGroup::new(num_neurons, num_neurons, connections)
+}
+
+/// Times `propagate` with presynaptic voltages that are zero except as the
+/// neurons spike, or with every neuron carrying a voltage on every step.
+fn bench_propagate(num_neurons: usize, continuous: bool) {
+    let dt = 0.001 * si::S;
+    let all_spikes: Vec<Vec<bool>> = (0..STEPS).map(|s| spikes(s, num_neurons)).collect();
+    let all_voltages: Vec<Vec<si::Volt<f64>>> = all_spikes
+        .iter()
+        .map(|step_spikes| {
+            step_spikes
+                .iter()
+                .map(|&spiked| {
+                    if continuous || spiked {
+                        0.5 * si::V
+                    } else {
+                        0.0 * si::V
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut group = sparse(num_neurons);
+    let mut inputs = vec![0.0 * si::V; num_neurons];
+    let start = Instant::now();
+    for (step_spikes, voltages) in all_spikes.iter().zip(all_voltages.iter()) {
+        group.propagate(voltages, &mut inputs);
+        group.on_pre_spikes(step_spikes, voltages);
+        group.advance(dt);
+    }
+    group.flush();
+    println!(
+        "{:>8} neurons, propagate with {} voltages: {:>10.3?} for {} steps",
+        num_neurons,
+        if continuous { "continuous" } else { "spiking" },
+        start.elapsed(),
+        STEPS
+    );
+}
+
+fn bench(num_neurons: usize, with_dense: bool) {
+    let dt = 0.001 * si::S;
+    let voltages = vec![0.5 * si::V; num_neurons];
+    let all_spikes: Vec<Vec<bool>> = (0..STEPS).map(|s| spikes(s, num_neurons)).collect();
+
+    let mut group = sparse(num_neurons);
+    let start = Instant::now();
+    for (step, step_spikes) in all_spikes.iter().enumerate() {
+        group.on_pre_spikes(step_spikes, &voltages);
+        group.on_post_spikes(&all_spikes[(step + 1) % STEPS], &voltages);
+        group.advance(dt);
+    }
+    group.flush();
+    let sparse_time = start.elapsed();
+    println!(
+        "{:>8} neurons, CSR:   {:>12} bytes, {:>10.3?} for {} steps",
+        num_neurons,
+        group.memory_usage(),
+        sparse_time,
+        STEPS
+    );
+
+    if with_dense {
+        let mut dense = Dense::new(num_neurons);
+        let start = Instant::now();
+        for (step, step_spikes) in all_spikes.iter().enumerate() {
+            dense.step(step_spikes, &all_spikes[(step + 1) % STEPS], dt);
+        }
+        let dense_time = start.elapsed();
+        println!(
+            "{:>8} neurons, dense: {:>12} bytes, {:>10.3?} for {} steps",
+            num_neurons,
+            Dense::memory_usage(num_neurons),
+            dense_time,
+            STEPS
+        );
+    } else {
+        println!(
+            "{:>8} neurons, dense: {:>12} bytes (too large to run)",
+            num_neurons,
+            Dense::memory_usage(num_neurons)
+        );
+    }
+}
+
+fn main() {
+    bench(1_000, true);
+    bench(2_000, true);
+    bench(100_000, false);
+    for &num_neurons in [1_000, 100_000].iter() {
+        bench_propagate(num_neurons, false);
+        bench_propagate(num_neurons, true);
+    }
+}
@@ -0,0 +1,419 @@
+//! Descriptions of models for review on paper. `describe()` renders a neuron
+//! or synapse as Markdown and `to_latex()` as LaTeX, both from the tokens
+//! that the macros compile, so that they cannot drift from the model.
+
+use quote::ToTokens;
+use syn::{BinOp, Block, Expr, Ident, Lit, Member, Stmt, Type, UnOp};
+
+use crate::macros::{IdentAndType, NeuronType};
+
+const GREEK: &[&str] = &[
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi", "psi",
+    "omega", "Gamma", "Delta", "Theta", "Lambda", "Xi", "Pi", "Sigma", "Upsilon", "Phi", "Psi",
+    "Omega",
+];
+
+/// The functions that LaTeX has a command for.
+const FUNCTIONS: &[&str] = &[
+    "exp", "ln", "sin", "cos", "tan", "sinh", "cosh", "tanh", "min", "max",
+];
+
+/// How some of the constants of `si` are written.
+const UNITS: &[(&str, &str)] = &[
+    ("S", "s"),
+    ("MS", "ms"),
+    ("HZ", "Hz"),
+    ("KG", "kg"),
+    ("M", "m"),
+    ("MOL", "mol"),
+];
+
+/// A variable: Greek letters are spelled out, and whatever follows the first
+/// `_` is a subscript, as are trailing digits, so `tau_m` is `\tau_{m}` and
+/// `x0` is `x_{0}`.
+pub fn latex_ident(name: &str) -> String {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (base, sub) = match name.find('_') {
+        Some(idx) if idx > 0 && idx + 1 < name.len() => (&name[..idx], Some(&name[idx + 1..])),
+        _ if digits > 0 && digits < name.len() => (
+            &name[..name.len() - digits],
+            Some(&name[name.len() - digits..]),
+        ),
+        _ => (name, None),
+    };
+    let base = if GREEK.contains(&base) {
+        format!("\\{}", base)
+    } else if base.chars().count() == 1 {
+        base.to_string()
+    } else {
+        format!("\\mathit{{{}}}", base.replace('_', "\\_"))
+    };
+    match sub {
+        Some(sub) if sub.chars().count() == 1 || sub.chars().all(|c| c.is_ascii_digit()) => {
+            format!("{}_{{{}}}", base, sub)
+        }
+        Some(sub) => format!("{}_{{\\mathrm{{{}}}}}", base, sub.replace('_', "\\_")),
+        None => base,
+    }
+}
+
+/// Escapes text, such as a type, for LaTeX outside of math.
+fn latex_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' => "\\textbackslash{}".to_string(),
+            '<' => "\\textless{}".to_string(),
+            '>' => "\\textgreater{}".to_string(),
+            '~' => "\\textasciitilde{}".to_string(),
+            '^' => "\\textasciicircum{}".to_string(),
+            '{' | '}' | '_' | '&' | '%' | '$' | '#' => format!("\\{}", c),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// A type as it would be written, rather than spaced out token by token.
+fn type_text(typ: &Type) -> String {
+    typ.to_token_stream()
+        .to_string()
+        .replace(' ', "")
+        .replace(',', ", ")
+}
+
+fn latex_lit(lit: &Lit) -> String {
+    let digits = match lit {
+        Lit::Float(f) => f.base10_digits().to_string(),
+        Lit::Int(i) => i.base10_digits().to_string(),
+        Lit::Bool(b) => return format!("\\mathrm{{{}}}", b.value),
+        _ => {
+            return format!(
+                "\\texttt{{{}}}",
+                latex_text(&lit.to_token_stream().to_string())
+            )
+        }
+    };
+    match digits.find(['e', 'E']) {
+        Some(idx) => format!(
+            "{} \\times 10^{{{}}}",
+            &digits[..idx],
+            digits[idx + 1..].trim_start_matches('+')
+        ),
+        None => digits,
+    }
+}
+
+/// The expression inside any parentheses, for where LaTeX groups it anyway,
+/// as in a fraction.
+fn unparenthesized(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Paren(paren) => unparenthesized(&paren.expr),
+        _ => expr,
+    }
+}
+
+/// Renders expressions, writing the given identifiers (such as the locals
+/// that `~x` and `xi` are rewritten into) as given.
+pub struct Renderer<'a> {
+    pub renames: &'a [(Ident, String)],
+}
+
+impl<'a> Renderer<'a> {
+    fn function(&self, name: &str, args: &[&Expr]) -> String {
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| self.expr(unparenthesized(arg)))
+            .collect();
+        match name {
+            "sqrt" => format!("\\sqrt{{{}}}", args.join(", ")),
+            "abs" => format!("\\left|{}\\right|", args.join(", ")),
+            "log10" => format!("\\log_{{10}}\\left({}\\right)", args.join(", ")),
+            _ if FUNCTIONS.contains(&name) => {
+                format!("\\{}\\left({}\\right)", name, args.join(", "))
+            }
+            _ => format!(
+                "\\operatorname{{{}}}\\left({}\\right)",
+                latex_text(name),
+                args.join(", ")
+            ),
+        }
+    }
+
+    fn binary(&self, op: &BinOp, left: &Expr, right: &Expr) -> String {
+        let op = match op {
+            BinOp::Add(_) | BinOp::AddEq(_) => "+",
+            BinOp::Sub(_) | BinOp::SubEq(_) => "-",
+            BinOp::Mul(_) | BinOp::MulEq(_) => "\\cdot",
+            BinOp::Div(_) | BinOp::DivEq(_) => {
+                return format!(
+                    "\\frac{{{}}}{{{}}}",
+                    self.expr(unparenthesized(left)),
+                    self.expr(unparenthesized(right))
+                )
+            }
+            BinOp::Rem(_) | BinOp::RemEq(_) => "\\bmod",
+            BinOp::And(_) => "\\land",
+            BinOp::Or(_) => "\\lor",
+            BinOp::Eq(_) => "=",
+            BinOp::Lt(_) => "<",
+            BinOp::Le(_) => "\\leq",
+            BinOp::Ne(_) => "\\neq",
+            BinOp::Ge(_) => "\\geq",
+            BinOp::Gt(_) => ">",
+            _ => {
+                return format!(
+                    "{} \\mathbin{{\\texttt{{{}}}}} {}",
+                    self.expr(left),
+                    latex_text(&op.to_token_stream().to_string()),
+                    self.expr(right)
+                )
+            }
+        };
+        format!("{} {} {}", self.expr(left), op, self.expr(right))
+    }
+
+    fn block(&self, block: &Block) -> String {
+        match block.stmts.as_slice() {
+            [Stmt::Expr(expr)] => self.expr(expr),
+            _ => format!(
+                "\\texttt{{{}}}",
+                latex_text(&block.to_token_stream().to_string())
+            ),
+        }
+    }
+
+    /// Renders an expression as LaTeX math. Parentheses are kept as written,
+    /// so the precedence is that of the Rust.
+    pub fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binary(binary) => self.binary(&binary.op, &binary.left, &binary.right),
+            Expr::AssignOp(assign) => format!(
+                "{} \\leftarrow {}",
+                self.expr(&assign.left),
+                self.binary(&assign.op, &assign.left, &assign.right)
+            ),
+            Expr::Assign(assign) => format!(
+                "{} \\leftarrow {}",
+                self.expr(&assign.left),
+                self.expr(&assign.right)
+            ),
+            Expr::Unary(unary) => match unary.op {
+                UnOp::Neg(_) => format!("-{}", self.expr(&unary.expr)),
+                UnOp::Not(_) => format!("\\lnot {}", self.expr(&unary.expr)),
+                UnOp::Deref(_) => self.expr(&unary.expr),
+            },
+            Expr::Paren(paren) => format!("\\left({}\\right)", self.expr(&paren.expr)),
+            Expr::Group(group) => self.expr(&group.expr),
+            Expr::Lit(lit) => latex_lit(&lit.lit),
+            Expr::Cast(cast) => self.expr(&cast.expr),
+            Expr::Path(path) => {
+                let segments: Vec<String> = path
+                    .path
+                    .segments
+                    .iter()
+                    .map(|s| s.ident.to_string())
+                    .collect();
+                let last = segments.last().cloned().unwrap_or_default();
+                if segments.len() == 1 {
+                    let ident = &path.path.segments[0].ident;
+                    if let Some((_, name)) = self.renames.iter().find(|(i, _)| i == ident) {
+                        return name.clone();
+                    }
+                    latex_ident(&last)
+                } else if last == "PI" {
+                    "\\pi".to_string()
+                } else {
+                    let unit = UNITS
+                        .iter()
+                        .find(|(constant, _)| *constant == last)
+                        .map_or(last.as_str(), |(_, unit)| unit);
+                    format!("\\mathrm{{{}}}", latex_text(unit))
+                }
+            }
+            Expr::Field(field) => {
+                let member = match &field.member {
+                    Member::Named(name) => name.to_string(),
+                    Member::Unnamed(index) => index.index.to_string(),
+                };
+                match &*field.base {
+                    Expr::Path(base) if base.path.is_ident("self") => latex_ident(&member),
+                    base => format!(
+                        "{}_{{\\mathrm{{{}}}}}",
+                        self.expr(base),
+                        latex_text(&member)
+                    ),
+                }
+            }
+            Expr::MethodCall(call) => {
+                let method = call.method.to_string();
+                match method.as_str() {
+                    "powi" | "powf" => format!(
+                        "{{{}}}^{{{}}}",
+                        self.expr(&call.receiver),
+                        call.args
+                            .iter()
+                            .map(|arg| self.expr(unparenthesized(arg)))
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                    _ => {
+                        let args: Vec<&Expr> = std::iter::once(&*call.receiver)
+                            .chain(call.args.iter())
+                            .collect();
+                        self.function(&method, &args)
+                    }
+                }
+            }
+            Expr::Call(call) => {
+                let name = match &*call.func {
+                    Expr::Path(path) => path
+                        .path
+                        .segments
+                        .last()
+                        .map(|s| s.ident.to_string())
+                        .unwrap_or_default(),
+                    func => func.to_token_stream().to_string(),
+                };
+                let args: Vec<&Expr> = call.args.iter().collect();
+                self.function(&name, &args)
+            }
+            Expr::Index(index) => {
+                format!("{}_{{{}}}", self.expr(&index.expr), self.expr(&index.index))
+            }
+            Expr::Block(block) => self.block(&block.block),
+            Expr::If(branch) => {
+                let otherwise = match &branch.else_branch {
+                    Some((_, otherwise)) => self.expr(otherwise),
+                    None => "0".to_string(),
+                };
+                format!(
+                    "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+                    self.block(&branch.then_branch),
+                    self.expr(&branch.cond),
+                    otherwise
+                )
+            }
+            _ => format!(
+                "\\texttt{{{}}}",
+                latex_text(&expr.to_token_stream().to_string())
+            ),
+        }
+    }
+}
+
+/// Renders an expression that does not use `~x` or noise.
+pub fn latex_expr(expr: &Expr) -> String {
+    Renderer { renames: &[] }.expr(expr)
+}
+
+enum Lines {
+    /// Declarations, as their name and type.
+    Table(Vec<(String, String)>),
+    /// Math, one equation or condition per line.
+    Math(Vec<String>),
+}
+
+/// A model, section by section, to render as Markdown or LaTeX.
+pub struct Description {
+    name: String,
+    // The kind of model, with its article, and its voltage and time types.
+    summary: (String, String, String),
+    sections: Vec<(&'static str, Lines)>,
+}
+
+impl Description {
+    /// Starts the description of a `kind`, such as a neuron, of the given
+    /// type.
+    pub fn new(kind: &'static str, typ: &NeuronType) -> Self {
+        Description {
+            name: typ.type_name.to_string(),
+            summary: (
+                match kind.chars().next() {
+                    Some('a') | Some('e') | Some('i') | Some('o') | Some('u') => {
+                        format!("An {}", kind)
+                    }
+                    _ => format!("A {}", kind),
+                },
+                type_text(&typ.voltage_type),
+                type_text(&typ.time_type),
+            ),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Adds a section of declarations, unless there are none.
+    pub fn table(&mut self, title: &'static str, declarations: &[IdentAndType]) {
+        if !declarations.is_empty() {
+            let rows = declarations
+                .iter()
+                .map(|d| (latex_ident(&d.name.to_string()), type_text(&d.type_name)))
+                .collect();
+            self.sections.push((title, Lines::Table(rows)));
+        }
+    }
+
+    /// Adds a section of math, unless there is none.
+    pub fn math(&mut self, title: &'static str, lines: Vec<String>) {
+        if !lines.is_empty() {
+            self.sections.push((title, Lines::Math(lines)));
+        }
+    }
+
+    pub fn markdown(&self) -> String {
+        let (kind, voltage, time) = &self.summary;
+        let mut out = format!(
+            "# {}\n\n{} with voltages in `{}` and times in `{}`.\n",
+            self.name, kind, voltage, time
+        );
+        for (title, lines) in self.sections.iter() {
+            out.push_str(&format!("\n## {}\n\n", title));
+            match lines {
+                Lines::Table(rows) => {
+                    out.push_str("| Name | Type |\n|---|---|\n");
+                    for (name, typ) in rows {
+                        out.push_str(&format!("| ${}$ | `{}` |\n", name, typ));
+                    }
+                }
+                Lines::Math(lines) => {
+                    let blocks: Vec<String> =
+                        lines.iter().map(|line| format!("$${}$$\n", line)).collect();
+                    out.push_str(&blocks.join("\n"));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn latex(&self) -> String {
+        let (kind, voltage, time) = &self.summary;
+        let mut out = format!(
+            "\\subsection*{{{}}}\n{} with voltages in \\texttt{{{}}} and times in \\texttt{{{}}}.\n",
+            latex_text(&self.name),
+            kind,
+            latex_text(voltage),
+            latex_text(time)
+        );
+        for (title, lines) in self.sections.iter() {
+            out.push_str(&format!("\n\\paragraph{{{}}}\n", title));
+            match lines {
+                Lines::Table(rows) => {
+                    out.push_str("\\begin{tabular}{ll}\n");
+                    for (name, typ) in rows {
+                        out.push_str(&format!(
+                            "${}$ & \\texttt{{{}}} \\\\\n",
+                            name,
+                            latex_text(typ)
+                        ));
+                    }
+                    out.push_str("\\end{tabular}\n");
+                }
+                Lines::Math(lines) => {
+                    out.push_str("\\begin{gather*}\n");
+                    out.push_str(&lines.join(" \\\\\n"));
+                    out.push_str("\n\\end{gather*}\n");
+                }
+            }
+        }
+        out
+    }
+}
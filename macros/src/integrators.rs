@@ -0,0 +1,463 @@
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_quote, Error, Ident, Type};
+
+use proc_macro2::{Literal, Span, TokenStream};
+
+use quote::{quote, ToTokens};
+
+/// How derivative equations (`x @ = ...`) are integrated over a time-step.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Forward Euler, the default.
+    Euler,
+    /// The second order (midpoint) Runge-Kutta method.
+    Rk2,
+    /// The classic fourth order Runge-Kutta method.
+    Rk4,
+    /// Solves each equation exactly as if it were linear in its own variable
+    /// (with the other variables held fixed), which suits gating variables.
+    ExponentialEuler,
+    /// Implicit Euler, solved with Newton's method, for stiff equations.
+    BackwardEuler,
+    /// Exact integration of linear (affine) equations, whatever the
+    /// time-step.
+    Exact,
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Euler => "euler",
+            Method::Rk2 => "rk2",
+            Method::Rk4 => "rk4",
+            Method::ExponentialEuler => "exponential_euler",
+            Method::BackwardEuler => "backward_euler",
+            Method::Exact => "exact",
+        }
+    }
+}
+
+impl Parse for Method {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name: Ident = input.parse()?;
+        let methods = [
+            Method::Euler,
+            Method::Rk2,
+            Method::Rk4,
+            Method::ExponentialEuler,
+            Method::BackwardEuler,
+            Method::Exact,
+        ];
+        match methods.iter().find(|m| name == m.name()) {
+            Some(method) => Ok(*method),
+            None => Err(Error::new(
+                name.span(),
+                format!(
+                    "Unknown integration method `{}`, expected one of {}",
+                    name,
+                    methods
+                        .iter()
+                        .map(|m| format!("`{}`", m.name()))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            )),
+        }
+    }
+}
+
+/// The constant `value` in the float type of a model: a plain literal for
+/// `f64` and `f32`, and a conversion with `Float::from_f64` for a type
+/// parameter.
+pub fn float_constant(value: f64, float: &Type) -> TokenStream {
+    match float.to_token_stream().to_string().as_str() {
+        "f64" => Literal::f64_unsuffixed(value).to_token_stream(),
+        "f32" => Literal::f32_suffixed(value as f32).to_token_stream(),
+        _ => {
+            let value = Literal::f64_unsuffixed(value);
+            quote!(<#float as ::brian_rs::units::Float>::from_f64(#value))
+        }
+    }
+}
+
+fn hygienic(prefix: &str, var: &Ident) -> Ident {
+    Ident::new(
+        &format!("{}_{}", prefix, var),
+        var.span().resolved_at(Span::mixed_site()),
+    )
+}
+
+fn hygienic_all(prefix: &str, vars: &[Ident]) -> Vec<Ident> {
+    vars.iter().map(|v| hygienic(prefix, v)).collect()
+}
+
+fn local(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+/// The argument of the derivative closures standing for the state variable
+/// `var`.
+pub fn stage_ident(var: &Ident) -> Ident {
+    hygienic("stage", var)
+}
+
+/// The largest value of the variable `var` so far, used by the adaptive
+/// driver to scale its errors.
+pub fn scale_ident(var: &Ident) -> Ident {
+    hygienic("scale", var)
+}
+
+/// Writes `let <prefix>_x = deriv_x(<state>);` for every variable `x`.
+fn derivatives_at(prefix: &str, vars: &[Ident], state: &[TokenStream]) -> TokenStream {
+    let mut lets = TokenStream::new();
+    for var in vars {
+        let name = hygienic(prefix, var);
+        let deriv = hygienic("deriv", var);
+        lets.extend(quote!(let #name = #deriv(#(#state),*);));
+    }
+    lets
+}
+
+/// The state `self.x + <prefix>_x * (<step>)` for every variable `x`.
+fn euler_step(prefix: &str, vars: &[Ident], step: &TokenStream) -> Vec<TokenStream> {
+    vars.iter()
+        .map(|var| {
+            let slope = hygienic(prefix, var);
+            quote!(self.#var + #slope * (#step))
+        })
+        .collect()
+}
+
+/// Defines the closures `deriv_x(<state>)` that evaluate the right-hand
+/// sides.
+fn derivative_closures(vars: &[Ident], types: &[Type], rhs: &[TokenStream]) -> TokenStream {
+    let stages: Vec<Ident> = vars.iter().map(stage_ident).collect();
+    let mut tokens = TokenStream::new();
+    for (var, rhs) in vars.iter().zip(rhs) {
+        let deriv = hygienic("deriv", var);
+        tokens.extend(quote!(let #deriv = |#(#stages: #types),*| #rhs;));
+    }
+    tokens
+}
+
+/// Integrates `x' = rhs` for the given variables over one step of `dt`,
+/// binding the next value of every variable `x` to `next(x)`.
+///
+/// The right-hand sides must read the state through `stage_ident` rather
+/// than `self`, so that they can be evaluated at intermediate states. Only
+/// Euler and the Runge-Kutta methods support floats other than `f64`. The
+/// `exact` method needs the terms of the equations instead, and is done by
+/// `exact_step`.
+pub fn integrate(
+    method: Method,
+    vars: &[Ident],
+    types: &[Type],
+    rhs: &[TokenStream],
+    next: &dyn Fn(&Ident) -> Ident,
+    float: &Type,
+) -> TokenStream {
+    let derivs: Vec<Ident> = vars.iter().map(|v| hygienic("deriv", v)).collect();
+    let nexts: Vec<Ident> = vars.iter().map(next).collect();
+    let current: Vec<TokenStream> = vars.iter().map(|v| quote!(self.#v)).collect();
+
+    let (half, two, sixth) = (
+        float_constant(0.5, float),
+        float_constant(2.0, float),
+        float_constant(6.0, float),
+    );
+    let mut tokens = derivative_closures(vars, types, rhs);
+    match method {
+        Method::Euler => {
+            tokens.extend(derivatives_at("k1", vars, &current));
+            let next_state = euler_step("k1", vars, &quote!(dt));
+            tokens.extend(quote!(#(let #nexts = #next_state;)*));
+        }
+        Method::Rk2 => {
+            tokens.extend(derivatives_at("k1", vars, &current));
+            let mid = euler_step("k1", vars, &quote!(dt * #half));
+            tokens.extend(derivatives_at("k2", vars, &mid));
+            let next_state = euler_step("k2", vars, &quote!(dt));
+            tokens.extend(quote!(#(let #nexts = #next_state;)*));
+        }
+        Method::Rk4 => {
+            tokens.extend(derivatives_at("k1", vars, &current));
+            let k2_state = euler_step("k1", vars, &quote!(dt * #half));
+            tokens.extend(derivatives_at("k2", vars, &k2_state));
+            let k3_state = euler_step("k2", vars, &quote!(dt * #half));
+            tokens.extend(derivatives_at("k3", vars, &k3_state));
+            let k4_state = euler_step("k3", vars, &quote!(dt));
+            tokens.extend(derivatives_at("k4", vars, &k4_state));
+            for (var, next) in vars.iter().zip(nexts.iter()) {
+                let (k1, k2, k3, k4) = (
+                    hygienic("k1", var),
+                    hygienic("k2", var),
+                    hygienic("k3", var),
+                    hygienic("k4", var),
+                );
+                tokens.extend(quote! {
+                    let #next = self.#var + (#k1 + #k2 * #two + #k3 * #two + #k4) * (dt / #sixth);
+                });
+            }
+        }
+        Method::ExponentialEuler => {
+            // With x' = a + b * x, the exact step is x + (a + b * x) * dt *
+            // (exp(b * dt) - 1) / (b * dt). The slope b is found from the
+            // change in x' over an Euler step.
+            tokens.extend(derivatives_at("f0", vars, &current));
+            for (idx, (var, next)) in vars.iter().zip(nexts.iter()).enumerate() {
+                let (f0, h, z) = (hygienic("f0", var), hygienic("h", var), hygienic("z", var));
+                let deriv = &derivs[idx];
+                let mut probe = current.clone();
+                probe[idx] = quote!(self.#var + #h);
+                tokens.extend(quote! {
+                    let #h = #f0 * dt;
+                    let #next = if #h == self.#var * 0.0 {
+                        self.#var
+                    } else {
+                        let #z = (#deriv(#(#probe),*) - #f0) / #h * dt;
+                        if #z.exp_m1() == 0.0 {
+                            self.#var + #h
+                        } else {
+                            self.#var + #h * #z.exp_m1() / #z
+                        }
+                    };
+                });
+            }
+        }
+        Method::BackwardEuler => {
+            // Solves y = x + x'(y) * dt for all the variables at once with
+            // Newton's method, starting from a forward Euler step. The solver
+            // works on the magnitudes of the variables.
+            let (y, residual) = (local("y"), local("residual"));
+            let indices: Vec<usize> = (0..vars.len()).collect();
+            let stages: Vec<Ident> = vars.iter().map(stage_ident).collect();
+            let count = vars.len();
+            tokens.extend(derivatives_at("f0", vars, &current));
+            let f0 = hygienic_all("f0", vars);
+            let residuals: Vec<TokenStream> = vars
+                .iter()
+                .zip(derivs.iter())
+                .zip(stages.iter())
+                .map(|((var, deriv), stage)| quote!(#stage - self.#var - #deriv(#(#stages),*) * dt))
+                .collect();
+            tokens.extend(quote! {
+                let mut #y = [#(
+                    ::brian_rs::reflect::Magnitude::to_magnitude(&(self.#vars + #f0 * dt))
+                ),*];
+                let #residual = |#y: &[f64; #count]| {
+                    #(
+                        let #stages = <#types as ::brian_rs::reflect::Magnitude>::from_magnitude(
+                            #y[#indices],
+                        );
+                    )*
+                    [#(
+                        ::brian_rs::reflect::Magnitude::to_magnitude(&(#residuals))
+                    ),*]
+                };
+                assert!(
+                    ::brian_rs::integrate::newton(&mut #y, #residual),
+                    "Backward Euler did not converge; try a shorter time-step"
+                );
+                #(
+                    let #nexts = <#types as ::brian_rs::reflect::Magnitude>::from_magnitude(
+                        #y[#indices],
+                    );
+                )*
+            });
+        }
+        Method::Exact => unreachable!("Exact integration is generated by `exact_step`"),
+    }
+    tokens
+}
+
+/// The field of a model integrated with the `exact` method that caches its
+/// propagator.
+pub fn propagator_field() -> Ident {
+    Ident::new("propagator", Span::call_site())
+}
+
+/// The type of `propagator_field` for a system of `count` variables.
+pub fn propagator_type(count: usize) -> Type {
+    parse_quote!(::brian_rs::integrate::Propagator<#count>)
+}
+
+/// The local standing for one unit of the variable `var` in the terms of a
+/// linear equation.
+pub fn unit_ident(var: &Ident) -> Ident {
+    hygienic("unit", var)
+}
+
+/// A term of the right-hand side of a linear equation, in the units of the
+/// derivative.
+pub struct LinearTerm {
+    /// The index of the variable that the term is proportional to, or `None`
+    /// for a term that does not depend on the variables.
+    pub var: Option<usize>,
+    /// The value of the term, with the variable it is proportional to
+    /// replaced by its `unit_ident`, so that this is its coefficient.
+    pub value: TokenStream,
+}
+
+/// One exact step of `dt` of the linear system `x' = A x + b`, binding the
+/// next value of every variable `x` to `next(x)`. Row `i` of `rows` holds
+/// the terms of the equation of `vars[i]`, and its `hold` condition, if
+/// any, zeroes the row while it is true.
+///
+/// `A dt` and `b dt` are computed from the terms as magnitudes, and the
+/// step itself is taken by the `Propagator` in `propagator_field`, which
+/// only computes `e^(A dt)` again when `A dt` changes.
+pub fn exact_step(
+    vars: &[Ident],
+    types: &[Type],
+    rows: &[(Vec<LinearTerm>, Option<TokenStream>)],
+    next: &dyn Fn(&Ident) -> Ident,
+) -> TokenStream {
+    let magnitude = quote!(::brian_rs::reflect::Magnitude);
+    let entry = |terms: Vec<&LinearTerm>, hold: &Option<TokenStream>| {
+        if terms.is_empty() {
+            return quote!(0.0);
+        }
+        let values = terms.iter().map(|t| &t.value);
+        let sum = quote!(#(#magnitude::to_magnitude(&((#values) * dt)))+*);
+        match hold {
+            Some(hold) => quote!(if #hold { 0.0 } else { #sum }),
+            None => sum,
+        }
+    };
+    let (matrix, constant): (Vec<TokenStream>, Vec<TokenStream>) = rows
+        .iter()
+        .map(|(terms, hold)| {
+            let row = (0..vars.len())
+                .map(|j| entry(terms.iter().filter(|t| t.var == Some(j)).collect(), hold));
+            (
+                quote!([#(#row),*]),
+                entry(terms.iter().filter(|t| t.var.is_none()).collect(), hold),
+            )
+        })
+        .unzip();
+    let used: Vec<usize> = (0..vars.len())
+        .filter(|j| rows.iter().any(|(terms, _)| terms.iter().any(|t| t.var == Some(*j))))
+        .collect();
+    let units = used.iter().map(|j| unit_ident(&vars[*j]));
+    let unit_types = used.iter().map(|j| &types[*j]);
+    let nexts = vars.iter().map(next);
+    let indices = 0..vars.len();
+    let (field, state) = (propagator_field(), local("state"));
+    quote! {
+        #(let #units = <#unit_types as #magnitude>::from_magnitude(1.0);)*
+        let #state = self.#field.step(
+            &[#(#matrix),*],
+            &[#(#constant),*],
+            &[#(#magnitude::to_magnitude(&self.#vars)),*],
+        );
+        #(let #nexts = <#types as #magnitude>::from_magnitude(#state[#indices]);)*
+    }
+}
+
+/// The Dormand-Prince tableau: the nodes are implied by the rows, which each
+/// give the weights of the earlier stages.
+const DORMAND_PRINCE: [&[f64]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0],
+    &[9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0],
+    &[35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+
+/// The weights of the fourth order solution used for the error estimate.
+const DORMAND_PRINCE_EMBEDDED: [f64; 7] = [
+    5179.0 / 57600.0,
+    0.0,
+    7571.0 / 16695.0,
+    393.0 / 640.0,
+    -92097.0 / 339200.0,
+    187.0 / 2100.0,
+    1.0 / 40.0,
+];
+
+/// The slope `w1 * k1_x + w2 * k2_x + ...` of the variable, skipping zero
+/// weights.
+fn weighted_slope(var: &Ident, weights: &[f64]) -> TokenStream {
+    let terms: Vec<TokenStream> = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| **w != 0.0)
+        .map(|(stage, w)| {
+            let k = hygienic(&format!("k{}", stage + 1), var);
+            quote!(#k * #w)
+        })
+        .collect();
+    quote!((#(#terms)+*))
+}
+
+/// The state `self.x + (w1 * k1_x + w2 * k2_x + ...) * dt`.
+fn weighted_step(vars: &[Ident], weights: &[f64]) -> Vec<TokenStream> {
+    vars.iter()
+        .map(|var| {
+            let slope = weighted_slope(var, weights);
+            quote!(self.#var + #slope * dt)
+        })
+        .collect()
+}
+
+/// One Dormand-Prince step of `dt`, binding the fifth order solution to
+/// `next(x)` and the largest relative error of a variable to `error`. The
+/// error of a variable is relative to the larger of its next value and its
+/// `scale_ident`.
+pub fn dormand_prince(
+    vars: &[Ident],
+    types: &[Type],
+    rhs: &[TokenStream],
+    next: &dyn Fn(&Ident) -> Ident,
+    error: &Ident,
+) -> TokenStream {
+    let current: Vec<TokenStream> = vars.iter().map(|v| quote!(self.#v)).collect();
+    let mut tokens = derivative_closures(vars, types, rhs);
+    tokens.extend(derivatives_at("k1", vars, &current));
+    for (stage, weights) in DORMAND_PRINCE.iter().enumerate() {
+        let state = weighted_step(vars, weights);
+        tokens.extend(derivatives_at(&format!("k{}", stage + 2), vars, &state));
+    }
+    // The last row is the fifth order solution, so k7 is its slope.
+    let nexts: Vec<Ident> = vars.iter().map(next).collect();
+    let solution = weighted_step(vars, DORMAND_PRINCE[5]);
+    tokens.extend(quote!(#(let #nexts = #solution;)*));
+
+    let mut error_weights = [0.0; 7];
+    for (stage, weight) in DORMAND_PRINCE[5].iter().enumerate() {
+        error_weights[stage] = *weight;
+    }
+    for (weight, embedded) in error_weights.iter_mut().zip(DORMAND_PRINCE_EMBEDDED.iter()) {
+        *weight -= embedded;
+    }
+    let errors: Vec<Ident> = hygienic_all("error", vars);
+    let error_steps: Vec<TokenStream> = vars
+        .iter()
+        .map(|var| {
+            let slope = weighted_slope(var, &error_weights);
+            quote!(#slope * dt)
+        })
+        .collect();
+    let scales: Vec<Ident> = vars.iter().map(scale_ident).collect();
+    let (var_error, ratio) = (local("var_error"), local("ratio"));
+    tokens.extend(quote! {
+        let mut #error = 0.0f64;
+        #(
+            let #errors = #error_steps;
+            if #errors * 0.0 != #errors {
+                let mut #var_error = f64::INFINITY;
+                if #scales * 0.0 != #scales {
+                    let #ratio: &f64 = &(#errors / #scales);
+                    #var_error = #var_error.min(#ratio.abs());
+                }
+                if #nexts * 0.0 != #nexts {
+                    let #ratio: &f64 = &(#errors / #nexts);
+                    #var_error = #var_error.min(#ratio.abs());
+                }
+                #error = #error.max(#var_error);
+            }
+        )*
+    });
+    tokens
+}
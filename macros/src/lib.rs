@@ -4,64 +4,405 @@ extern crate quote;
 extern crate syn;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::parse_macro_input;
-use syn::Ident;
+use proc_macro2::{Literal, Span};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{parse_macro_input, parse_quote};
+use syn::spanned::Spanned;
+use syn::{Ident, Type};
 
+mod describe;
+mod integrators;
 mod macros;
+mod network;
 
-use crate::macros::{IdentAndType, NeuronDef, NeuronType, SynapseDef};
+use crate::integrators::float_constant;
+use crate::macros::{
+    adaptive_driver, expression_checks, expression_getters, generic_bounds,
+    index_fields, mentions, noise_field, noise_terms, propagator, reflect_impl, refractory_field,
+    simultaneous_update, size_field, IdentAndType, InputGeneratorDef, NeuronDef, NeuronType,
+    SynapseDef,
+};
+use crate::network::{expand_network, NetworkDef};
 
 #[proc_macro]
 pub fn define_neuron(input: TokenStream) -> TokenStream {
+    expand_neuron(parse_macro_input!(input as NeuronDef), false).into()
+}
+
+/// Like `define_neuron!`, but for input neurons, which have no `input`: they
+/// implement `InputSpikeGenerator` and are stepped with `advance`.
+#[proc_macro]
+pub fn define_input_generator(input: TokenStream) -> TokenStream {
+    let InputGeneratorDef(def) = parse_macro_input!(input as InputGeneratorDef);
+    expand_neuron(def, true).into()
+}
+
+/// The code for a neuron, or for an input generator, which steps with no
+/// input.
+fn expand_neuron(def: NeuronDef, input_generator: bool) -> proc_macro2::TokenStream {
     let NeuronDef {
         neuron_type,
         param_list,
         initialize_list,
+        inputs,
+        expressions,
+        method,
         time_step,
         spike_when,
         voltage_getter,
         reset,
-    } = parse_macro_input!(input as NeuronDef);
+        refractory,
+        description,
+    } = def;
+    // Note: voltage means electric voltage.
+    let NeuronType {
+        type_name,
+        voltage_type,
+        time_type,
+        float_type,
+        ..
+    } = &neuron_type;
     let field_names: Vec<Ident> = param_list.iter().map(|i| i.name.clone()).collect();
+    // Neurons with noise terms sample them from a `Noise` passed to `new`.
+    let noise_field = if noise_terms(&time_step).is_empty() {
+        None
+    } else {
+        Some(IdentAndType {
+            name: noise_field(),
+            type_name: parse_quote!(::brian_rs::noise::Noise),
+        })
+    };
+    let noise_param: Vec<&IdentAndType> = noise_field.iter().collect();
+    let noise_name: Vec<&Ident> = noise_field.iter().map(|f| &f.name).collect();
+    let propagator = propagator(&time_step, method);
+    let propagator_name: Vec<&Ident> = propagator.iter().map(|f| &f.name).collect();
     let typed_fields: Vec<IdentAndType> = param_list
         .clone()
         .into_iter()
         .chain(initialize_list.clone().into_iter().map(|i| i.drop_value()))
+        .chain(refractory.iter().map(|r| r.field(time_type)))
+        .chain(noise_field.clone())
+        .chain(propagator.clone())
         .collect();
-    // Note: voltage means electric voltage.
-    let NeuronType {
-        type_name,
-        voltage_type,
-        time_type,
-    } = neuron_type;
+
+    // The expressions are checked in a closure that is never called, so that
+    // the checks cost nothing. The equations, `spike_when` and `get_voltage`
+    // are checked where they are evaluated, by binding them to a local of
+    // the type they must have, spanned to the block.
+    let expression_checks = expression_checks(&expressions);
+    let type_checks = quote!(let _ = || { #expression_checks };);
+    let (spiking, voltage) = (
+        Ident::new("spiking", Span::mixed_site()),
+        Ident::new("voltage", Span::mixed_site()),
+    );
+    let spike_when_check = quote_spanned!(spike_when.span()=> let #spiking: bool = #spike_when;);
+    let spike_when = quote!({ #spike_when_check #spiking });
+    let voltage_check =
+        quote_spanned!(voltage_getter.span()=> let #voltage: #voltage_type = #voltage_getter;);
+    let voltage_getter = quote!({ #voltage_check #voltage });
+
+    // A refractory neuron cannot spike, and its refractory period starts
+    // with every reset and counts down with every step.
+    let refractory_field = refractory_field();
+    let (spike_when, refractory_init) = match refractory {
+        Some(_) => (
+            quote!(self.#refractory_field.is_none() && #spike_when),
+            Some(quote!(#refractory_field: None,)),
+        ),
+        None => (spike_when.to_token_stream(), None),
+    };
+    let start = refractory.as_ref().map(|r| r.start());
+    let on_spike = quote! {
+        #reset
+        #start
+    };
+
+    // The group stores every field in its own `Vec` and runs the same blocks
+    // with `self.x` rewritten to `self.x[idx]`.
+    let group_name = format_ident!("{}Group", type_name);
+    let all_names: Vec<Ident> = typed_fields.iter().map(|f| f.name.clone()).collect();
+    let all_types: Vec<Type> = typed_fields.iter().map(|f| f.type_name.clone()).collect();
+    // The group's own locals are hygienic, so they cannot clash with names
+    // in the blocks it runs.
+    let idx = Ident::new("neuron_idx", Span::mixed_site());
+    let (size, neurons, neuron, group, spiked, inputs_arg) = (
+        size_field(),
+        Ident::new("neurons", Span::mixed_site()),
+        Ident::new("neuron", Span::mixed_site()),
+        Ident::new("group", Span::mixed_site()),
+        Ident::new("spiked", Span::mixed_site()),
+        Ident::new("inputs", Span::mixed_site()),
+    );
+    let indexed = |tokens: proc_macro2::TokenStream| index_fields(tokens, &all_names, &idx);
+    let group_spike_when = indexed(spike_when.clone());
+    let group_voltage_getter = indexed(voltage_getter.clone());
+    let group_on_spike = indexed(on_spike.clone());
+    let getters = expression_getters(&expressions, None);
+    let (markdown, latex) = (description.markdown(), description.latex());
+    let group_getters = expression_getters(&expressions, Some((&idx, &all_names)));
+
+    // With an `inputs` section, the neuron is stepped with a struct holding
+    // one input per port, and `handle_input` feeds the first port.
+    let inputs_name = format_ident!("{}Inputs", type_name);
+    // The struct only takes the type parameters that its ports use.
+    let input_params: Vec<&Ident> = neuron_type
+        .type_params()
+        .into_iter()
+        .filter(|param| inputs.iter().any(|i| mentions(i.type_name.to_token_stream(), param)))
+        .collect();
+    let input_generics = if input_params.is_empty() {
+        None
+    } else {
+        Some(quote!(<#(#input_params),*>))
+    };
+    let input_type: Type = if inputs.is_empty() {
+        voltage_type.clone()
+    } else {
+        parse_quote!(#inputs_name #input_generics)
+    };
+
+    // `advance_adaptive` steps with a constant input, so input generators
+    // do not get it.
+    let adaptive = if input_generator {
+        proc_macro2::TokenStream::new()
+    } else {
+        match adaptive_driver(
+            &time_step,
+            &typed_fields,
+            &on_spike,
+            refractory.as_ref(),
+            &input_type,
+            &neuron_type,
+        ) {
+            Ok(adaptive) => adaptive,
+            Err(err) => return err.to_compile_error(),
+        }
+    };
+    // The impls of a neuron with type parameters also carry the bounds its
+    // integrator needs.
+    let generics = generic_bounds(
+        &neuron_type,
+        &time_step,
+        &typed_fields,
+        refractory.is_some(),
+        &inputs,
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let struct_generics = &neuron_type.generics;
+    let (phantom_decl, phantom_init) = neuron_type.phantom();
+    let state_vars: Vec<IdentAndType> = initialize_list.iter().map(|i| i.drop_value()).collect();
+    let reflect = reflect_impl(&neuron_type, &generics, &param_list, &state_vars);
+
+    let time_step = match simultaneous_update(&time_step, method, &typed_fields, &neuron_type) {
+        Ok(time_step) => time_step,
+        Err(err) => return err.to_compile_error(),
+    };
+    let time_step = match &refractory {
+        Some(refractory) => {
+            let (begin, end) = (refractory.begin_step(), refractory.end_step(float_type));
+            quote!(#begin #time_step #end)
+        }
+        None => time_step,
+    };
+    let group_time_step = indexed(time_step.clone());
+    let step = quote! {
+        #type_checks
+        if self.did_spike() { #on_spike }
+        #time_step
+    };
+
+    let (handle_input, inputs_struct, handle_named_inputs) = if input_generator {
+        (
+            quote! {
+                fn advance(&mut self, dt: #time_type) {
+                    #step
+                }
+            },
+            None,
+            None,
+        )
+    } else if inputs.is_empty() {
+        (
+            quote! {
+                fn handle_input(&mut self, input: #voltage_type, dt: #time_type) {
+                    #step
+                }
+            },
+            None,
+            None,
+        )
+    } else {
+        let ports: Vec<&Ident> = inputs.iter().map(|p| &p.name).collect();
+        let port_names: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+        let port_idx = (0..ports.len()).map(Literal::usize_unsuffixed);
+        let (first, rest) = (ports[0], &ports[1..]);
+        let num_ports = ports.len();
+        let zero = float_constant(0.0, float_type);
+        (
+            quote! {
+                fn handle_input(&mut self, input: #voltage_type, dt: #time_type) {
+                    self.handle_named_inputs(#inputs_name { #first: input, #(#rest: input * #zero),* }, dt)
+                }
+
+                fn ports(&self) -> &'static [&'static str] {
+                    &[#(#port_names),*]
+                }
+
+                fn handle_port_inputs(&mut self, inputs: &[#voltage_type], dt: #time_type) {
+                    assert_eq!(inputs.len(), #num_ports, "Expected one input per port");
+                    self.handle_named_inputs(#inputs_name { #(#ports: inputs[#port_idx]),* }, dt)
+                }
+            },
+            Some(quote! {
+                /// The inputs of a neuron, one per input port.
+                #[derive(Clone, Copy)]
+                pub struct #inputs_name #input_generics {
+                    #(pub #inputs),*
+                }
+            }),
+            Some(quote! {
+                /// Steps the neuron with one input per port.
+                pub fn handle_named_inputs(&mut self, input: #input_type, dt: #time_type) {
+                    #step
+                }
+            }),
+        )
+    };
+
+    let stepper = if input_generator {
+        quote!(InputSpikeGenerator)
+    } else {
+        quote!(InnerSpikeGenerator)
+    };
+    let group_step = if input_generator {
+        quote! {
+            /// Advances every generator, as the generator itself would, and
+            /// returns the indices of the generators that spiked.
+            pub fn advance(&mut self, dt: #time_type) -> Vec<usize> {
+                let mut #spiked = Vec::new();
+                for #idx in 0..self.#size {
+                    if self.did_spike(#idx) { #group_on_spike }
+                    #group_time_step
+                    if self.did_spike(#idx) {
+                        #spiked.push(#idx);
+                    }
+                }
+                #spiked
+            }
+        }
+    } else {
+        quote! {
+            /// Steps every neuron with its input, as the neuron itself would,
+            /// and returns the indices of the neurons that spiked.
+            pub fn handle_inputs(&mut self, #inputs_arg: &[#input_type], dt: #time_type) -> Vec<usize> {
+                assert_eq!(
+                    #inputs_arg.len(),
+                    self.#size,
+                    "Expected one input per neuron"
+                );
+                let mut #spiked = Vec::new();
+                for #idx in 0..self.#size {
+                    let input = #inputs_arg[#idx];
+                    if self.did_spike(#idx) { #group_on_spike }
+                    #group_time_step
+                    if self.did_spike(#idx) {
+                        #spiked.push(#idx);
+                    }
+                }
+                #spiked
+            }
+        }
+    };
+
     let expanded = quote! {
-    pub struct #type_name {
-        #(#typed_fields),*
+    pub struct #type_name #struct_generics {
+        #(#typed_fields,)*
+        #phantom_decl
     }
 
-    impl #type_name {
-        pub fn new(#(#param_list),*) -> Self {
+    #inputs_struct
+
+    // The trait impls come before the inherent one so that the checks in
+    // the step are reported before the errors they cause elsewhere.
+    impl #impl_generics SpikeGenerator<#voltage_type> for #type_name #ty_generics #where_clause {
+        fn did_spike(&self) -> bool { #spike_when }
+        fn get_voltage(&self) -> #voltage_type { #voltage_getter }
+    }
+
+    impl #impl_generics #stepper<#voltage_type, #time_type> for #type_name #ty_generics #where_clause {
+        #handle_input
+    }
+
+    impl #impl_generics #type_name #ty_generics #where_clause {
+        pub fn new(#(#param_list),* #(, #noise_param)*) -> Self {
             #type_name {
         #(#field_names),*,
+        #(#noise_name,)*
+        #(#propagator_name: ::brian_rs::integrate::Propagator::new(),)*
+        #phantom_init
+        #refractory_init
         #(#initialize_list),*
             }
         }
-    }
 
-    impl SpikeGenerator<#voltage_type> for #type_name {
-        fn did_spike(&self) -> bool { #spike_when }
-        fn get_voltage(&self) -> #voltage_type { #voltage_getter }
+        #handle_named_inputs
+
+        #getters
+
+        /// The model as Markdown, with the math in LaTeX, for review.
+        pub fn describe() -> &'static str {
+            #markdown
+        }
+
+        /// The model as a LaTeX fragment, for review.
+        pub fn to_latex() -> &'static str {
+            #latex
+        }
+
+        #adaptive
     }
 
-    impl InnerSpikeGenerator<#voltage_type, #time_type> for #type_name {
-        fn handle_input(&mut self, input: #voltage_type, dt: #time_type) {
-        if self.did_spike() { #(#reset);*; }
-        #(#time_step);*;
+    #reflect
+
+    /// A population of neurons stored as a struct of arrays: each field of
+    /// the neuron is kept in its own `Vec`.
+    pub struct #group_name #struct_generics {
+        #size: usize,
+        #phantom_decl
+        #(#all_names: Vec<#all_types>),*
     }
+
+    impl #impl_generics #group_name #ty_generics #where_clause {
+        pub fn new(#neurons: Vec<#type_name #ty_generics>) -> Self {
+            let mut #group = #group_name {
+                #size: #neurons.len(),
+                #phantom_init
+                #(#all_names: Vec::with_capacity(#neurons.len())),*
+            };
+            for #neuron in #neurons {
+                #(#group.#all_names.push(#neuron.#all_names);)*
+            }
+            #group
+        }
+
+        pub fn len(&self) -> usize {
+            self.#size
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.#size == 0
+        }
+
+        pub fn did_spike(&self, #idx: usize) -> bool { #group_spike_when }
+
+        pub fn get_voltage(&self, #idx: usize) -> #voltage_type { #group_voltage_getter }
+
+        #group_getters
+
+        #group_step
     }
     };
-    expanded.into()
+    expanded
 }
 
 #[proc_macro]
@@ -70,46 +411,93 @@ pub fn define_synapse(input: TokenStream) -> TokenStream {
         synapse_type,
         param_list,
         initialize_list,
+        expressions,
+        method,
 	time_step,
         weight_getter,
         pre_synapse_spike,
         post_synapse_spike,
+        description,
     } = parse_macro_input!(input as SynapseDef);
     let field_names: Vec<Ident> = param_list.iter().map(|i| i.name.clone()).collect();
+    let propagator = propagator(&time_step, method);
+    let propagator_name: Vec<&Ident> = propagator.iter().map(|f| &f.name).collect();
     let typed_fields: Vec<IdentAndType> = param_list
         .clone()
         .into_iter()
         .chain(initialize_list.clone().into_iter().map(|i| i.drop_value()))
+        .chain(propagator.clone())
         .collect();
     // Note: voltage means electric voltage.
     let NeuronType {
         type_name,
         voltage_type,
         time_type,
-    } = synapse_type;
+        ..
+    } = &synapse_type;
+    let expression_checks = expression_checks(&expressions);
+    let getters = expression_getters(&expressions, None);
+    let (markdown, latex) = (description.markdown(), description.latex());
+    let generics = generic_bounds(&synapse_type, &time_step, &typed_fields, false, &[]);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let struct_generics = &synapse_type.generics;
+    let (phantom_decl, phantom_init) = synapse_type.phantom();
+    let state_vars: Vec<IdentAndType> = initialize_list.iter().map(|i| i.drop_value()).collect();
+    let reflect = reflect_impl(&synapse_type, &generics, &param_list, &state_vars);
+    let time_step = match simultaneous_update(&time_step, method, &typed_fields, &synapse_type) {
+        Ok(time_step) => time_step,
+        Err(err) => return err.to_compile_error().into(),
+    };
     let expanded = quote!{
-    pub struct #type_name {
-        #(#typed_fields),*
+    pub struct #type_name #struct_generics {
+        #(#typed_fields,)*
+        #phantom_decl
     }
 
-    impl #type_name {
+    impl #impl_generics #type_name #ty_generics #where_clause {
         pub fn new(#(#param_list),*) -> Self {
             #type_name {
         #(#field_names),*,
+        #(#propagator_name: ::brian_rs::integrate::Propagator::new(),)*
+        #phantom_init
         #(#initialize_list),*
             }
         }
+
+        #getters
+
+        /// The model as Markdown, with the math in LaTeX, for review.
+        pub fn describe() -> &'static str {
+            #markdown
+        }
+
+        /// The model as a LaTeX fragment, for review.
+        pub fn to_latex() -> &'static str {
+            #latex
+        }
     }
 
-    impl Synaptic<#voltage_type, #time_type> for #type_name {
-        fn on_pre(&mut self, input: #voltage_type) { #(#pre_synapse_spike);*; }	    
-        fn on_post(&mut self, input: #voltage_type) { #(#post_synapse_spike);*; }
+    impl #impl_generics Synaptic<#voltage_type, #time_type> for #type_name #ty_generics #where_clause {
+        fn on_pre(&mut self, input: #voltage_type) { #pre_synapse_spike }	    
+        fn on_post(&mut self, input: #voltage_type) { #post_synapse_spike }
 	fn current_weight(&self) -> f64 { #weight_getter }
 
 	fn advance_once(&mut self, dt: #time_type) {
-            #(#time_step);*;
+            // Never called, so the checks cost nothing.
+            let _ = || { #expression_checks };
+            #time_step
 	}
     }
+
+    #reflect
     };
     expanded.into()
 }
+
+/// A network of populations of neurons joined by projections of synapses,
+/// as a struct with a field for each, so that the whole model is checked
+/// when it is compiled.
+#[proc_macro]
+pub fn define_network(input: TokenStream) -> TokenStream {
+    expand_network(parse_macro_input!(input as NetworkDef)).into()
+}
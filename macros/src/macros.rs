@@ -1,19 +1,31 @@
 use syn::braced;
 use syn::parse::{Parse, ParseStream, Result};
 use syn::punctuated::Punctuated;
-use syn::{Error, Expr, Ident, Token, Type};
+use syn::{
+    parse_quote, Attribute, BinOp, Error, Expr, GenericParam, Generics, Ident, Member, Token, Type,
+    TypeParam, UnOp,
+};
 
-use proc_macro2::{Punct, Spacing, TokenStream};
+use std::cell::Cell;
+
+use proc_macro2::{Delimiter, Group, Punct, Spacing, Span, TokenStream, TokenTree};
 
-use quote::ToTokens;
 use quote::TokenStreamExt;
+use quote::{quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
+
+use crate::describe::{latex_expr, latex_ident, Description, Renderer};
+use crate::integrators::{
+    dormand_prince, exact_step, float_constant, integrate, propagator_field, propagator_type,
+    scale_ident, stage_ident, unit_ident, LinearTerm, Method,
+};
 
 // Thanks Obama
 fn syn_ident_to_proc2(syn_id: &Ident) -> proc_macro2::Ident {
     proc_macro2::Ident::new(&syn_id.to_string(), syn_id.span())
 }
 
-fn expect_str(input: &ParseStream, s: &str) -> Result<()> {
+pub fn expect_str(input: &ParseStream, s: &str) -> Result<()> {
     let ident: Ident = input.parse()?;
     if ident.to_string() != s {
         return Err(Error::new(
@@ -24,7 +36,209 @@ fn expect_str(input: &ParseStream, s: &str) -> Result<()> {
     Ok(())
 }
 
-fn get_delimited_within_braces<T: Parse, Delim: syn::token::Token + Parse>(
+/// The sections of `define_neuron!`, in order. `inputs`, `expressions`,
+/// `method` and `refractory` may be left out.
+const NEURON_SECTIONS: &[&str] = &[
+    "params",
+    "initialize",
+    "inputs",
+    "expressions",
+    "method",
+    "time_step",
+    "spike_when",
+    "get_voltage",
+    "reset",
+    "refractory",
+];
+
+/// The sections of `define_input_generator!`, in order: those of
+/// `define_neuron!` but `inputs`.
+const INPUT_GENERATOR_SECTIONS: &[&str] = &[
+    "params",
+    "initialize",
+    "expressions",
+    "method",
+    "time_step",
+    "spike_when",
+    "get_voltage",
+    "reset",
+    "refractory",
+];
+
+/// The sections of `define_synapse!`, in order. `expressions` and `method`
+/// may be left out.
+const SYNAPSE_SECTIONS: &[&str] = &[
+    "params",
+    "initialize",
+    "expressions",
+    "method",
+    "time_step",
+    "weight_getter",
+    "on_pre",
+    "on_post",
+];
+
+/// Like `expect_str` for the keyword of a section, but says so when the
+/// section that was found is out of order.
+pub fn expect_section(input: &ParseStream, section: &str, sections: &[&str]) -> Result<()> {
+    let ident: Ident = input.parse()?;
+    if ident == section {
+        return Ok(());
+    }
+    let position = |name: &str| sections.iter().position(|s| *s == name);
+    let message = match (position(&ident.to_string()), position(section)) {
+        (Some(found), Some(expected)) if found > expected => {
+            format!("Expected `{}` before `{}`", section, ident)
+        }
+        (Some(_), _) => format!(
+            "`{}` is out of order; the sections are {}",
+            ident,
+            sections
+                .iter()
+                .map(|s| format!("`{}`", s))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        (None, _) => format!("Expected `{}` keyword", section),
+    };
+    Err(Error::new(ident.span(), message))
+}
+
+/// Replaces every `self.<field>` in `tokens`, for the given fields, with
+/// `replace(field)`.
+pub fn map_self_fields(
+    tokens: TokenStream,
+    fields: &[Ident],
+    replace: &dyn Fn(&Ident) -> TokenStream,
+) -> TokenStream {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut mapped = TokenStream::new();
+    let mut i = 0;
+    while i < toks.len() {
+        match &toks[i] {
+            TokenTree::Group(group) => {
+                let mut new_group = Group::new(
+                    group.delimiter(),
+                    map_self_fields(group.stream(), fields, replace),
+                );
+                new_group.set_span(group.span());
+                mapped.append(new_group);
+            }
+            TokenTree::Ident(id) if id == "self" && i + 2 < toks.len() => {
+                if let (TokenTree::Punct(dot), TokenTree::Ident(field)) = (&toks[i + 1], &toks[i + 2])
+                {
+                    if dot.as_char() == '.' && fields.contains(field) {
+                        mapped.extend(replace(field));
+                        i += 3;
+                        continue;
+                    }
+                }
+                mapped.append(toks[i].clone());
+            }
+            tok => mapped.append(tok.clone()),
+        }
+        i += 1;
+    }
+    mapped
+}
+
+/// Rewrites every `self.<field>` in `tokens` into `self.<field>[<index>]`, so
+/// that code written for one neuron can run on a group that stores each field
+/// in its own `Vec`. The original tokens are kept, and the index has the span
+/// of the field, so that errors in the group's copy of the code are reported
+/// at the same places as in the neuron's, and only once.
+pub fn index_fields(tokens: TokenStream, fields: &[Ident], index: &Ident) -> TokenStream {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut indexed = TokenStream::new();
+    for (i, tok) in toks.iter().enumerate() {
+        match tok {
+            TokenTree::Group(group) => {
+                let mut new_group =
+                    Group::new(group.delimiter(), index_fields(group.stream(), fields, index));
+                new_group.set_span(group.span());
+                indexed.append(new_group);
+            }
+            TokenTree::Ident(field) if i >= 2 && fields.contains(field) => {
+                indexed.append(tok.clone());
+                if let (TokenTree::Ident(base), TokenTree::Punct(dot)) =
+                    (&toks[i - 2], &toks[i - 1])
+                {
+                    if base == "self" && dot.as_char() == '.' {
+                        let mut brackets = Group::new(Delimiter::Bracket, index.to_token_stream());
+                        brackets.set_span(field.span());
+                        indexed.append(brackets);
+                    }
+                }
+            }
+            tok => indexed.append(tok.clone()),
+        }
+    }
+    indexed
+}
+
+/// Whether `ident` appears anywhere in `tokens`.
+pub fn mentions(tokens: TokenStream, ident: &Ident) -> bool {
+    tokens.into_iter().any(|tok| match tok {
+        TokenTree::Group(group) => mentions(group.stream(), ident),
+        TokenTree::Ident(id) => &id == ident,
+        _ => false,
+    })
+}
+
+/// Checks that `tokens` do not use `input`, for input generators, which
+/// have none.
+fn check_no_input(tokens: TokenStream) -> Result<()> {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, tok) in toks.iter().enumerate() {
+        match tok {
+            TokenTree::Group(group) => check_no_input(group.stream())?,
+            TokenTree::Ident(id) if id == "input" => {
+                let is_field = match i.checked_sub(1).map(|j| &toks[j]) {
+                    Some(TokenTree::Punct(dot)) => dot.as_char() == '.',
+                    _ => false,
+                };
+                if !is_field {
+                    return Err(Error::new(
+                        id.span(),
+                        "Input generators have no `input`; they only depend on their own state",
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every `self.<field>` in `tokens` (other than method calls) is
+/// one of the given fields.
+fn check_self_fields(tokens: TokenStream, fields: &[&Ident]) -> Result<()> {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, tok) in toks.iter().enumerate() {
+        match tok {
+            TokenTree::Group(group) => check_self_fields(group.stream(), fields)?,
+            TokenTree::Ident(id) if id == "self" && i + 2 < toks.len() => {
+                let is_call = match toks.get(i + 3) {
+                    Some(TokenTree::Group(group)) => group.delimiter() == Delimiter::Parenthesis,
+                    _ => false,
+                };
+                if let (TokenTree::Punct(dot), TokenTree::Ident(field)) = (&toks[i + 1], &toks[i + 2])
+                {
+                    if dot.as_char() == '.' && !is_call && !fields.contains(&field) {
+                        return Err(Error::new(
+                            field.span(),
+                            format!("`{}` is not a parameter or a state variable", field),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn get_delimited_within_braces<T: Parse, Delim: syn::token::Token + Parse>(
     input: &ParseStream,
 ) -> Result<Vec<T>> {
     let inits_toks;
@@ -36,24 +250,91 @@ fn get_delimited_within_braces<T: Parse, Delim: syn::token::Token + Parse>(
     )
 }
 
+/// The name and types of a neuron or synapse: `Name<Voltage, Time>`, or
+/// `Name<Voltage, Time, Float>` to compute in another float than `f64`. Any
+/// of the types may be a type parameter with bounds instead, as in
+/// `Lif<V: Quantity<F>, T: Quantity<F>, F: Float>`.
+///
+/// The name may be preceded by `#[reflect]` to implement `Reflect` for the
+/// type, which needs every parameter and state variable to be `Magnitude`.
 pub struct NeuronType {
+    pub reflect: bool,
     pub type_name: Ident,
+    pub generics: Generics,
     pub voltage_type: Type,
     pub time_type: Type,
+    pub float_type: Type,
+}
+
+impl NeuronType {
+    /// Whether the type has no type parameters and is computed in `f64`,
+    /// which every part of the macros supports.
+    pub fn is_plain(&self) -> bool {
+        self.generics.params.is_empty() && self.float_type.to_token_stream().to_string() == "f64"
+    }
+
+    /// The type parameters, without their bounds.
+    pub fn type_params(&self) -> Vec<&Ident> {
+        self.generics.type_params().map(|p| &p.ident).collect()
+    }
+
+    /// The declaration of the `phantom` field and its value in `new`, for
+    /// types with type parameters.
+    pub fn phantom(&self) -> (Option<TokenStream>, Option<TokenStream>) {
+        let params = self.type_params();
+        if params.is_empty() {
+            return (None, None);
+        }
+        let phantom = phantom_field();
+        (
+            Some(quote!(#phantom: std::marker::PhantomData<(#(#params,)*)>,)),
+            Some(quote!(#phantom: std::marker::PhantomData,)),
+        )
+    }
+}
+
+/// Parses a type, or a type parameter (`T: Bounds`) that it adds to
+/// `generics` and stands for.
+fn parse_type_or_param(input: ParseStream, generics: &mut Generics) -> Result<Type> {
+    if input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
+        let param: TypeParam = input.parse()?;
+        let ident = param.ident.clone();
+        generics.params.push(GenericParam::Type(param));
+        Ok(parse_quote!(#ident))
+    } else {
+        input.parse()
+    }
 }
 
 impl Parse for NeuronType {
     fn parse(input: ParseStream) -> Result<Self> {
+        let mut reflect = false;
+        for attr in Attribute::parse_outer(input)? {
+            if !attr.path.is_ident("reflect") || !attr.tokens.is_empty() {
+                return Err(Error::new_spanned(attr, "The only attribute is `#[reflect]`"));
+            }
+            reflect = true;
+        }
         let type_name: Ident = input.parse()?;
+        let mut generics = Generics::default();
         input.parse::<Token![<]>()?;
-        let voltage_type: Type = input.parse()?;
+        let voltage_type = parse_type_or_param(input, &mut generics)?;
         input.parse::<Token![,]>()?;
-        let time_type: Type = input.parse()?;
+        let time_type = parse_type_or_param(input, &mut generics)?;
+        let float_type = if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            parse_type_or_param(input, &mut generics)?
+        } else {
+            parse_quote!(f64)
+        };
         input.parse::<Token![>]>()?;
         Ok(NeuronType {
+            reflect,
             type_name,
+            generics,
             voltage_type,
             time_type,
+            float_type,
         })
     }
 }
@@ -61,7 +342,7 @@ impl Parse for NeuronType {
 #[derive(Clone)]
 pub struct IdentAndType {
     pub name: Ident,
-    type_name: Type,
+    pub type_name: Type,
 }
 
 impl Parse for IdentAndType {
@@ -92,6 +373,11 @@ pub struct IdentAndValue {
 }
 
 impl IdentAndValue {
+    /// The declaration as LaTeX: `name = value`.
+    pub fn latex(&self) -> String {
+        format!("{} = {}", latex_ident(&self.name.to_string()), latex_expr(&self.value))
+    }
+
     pub fn drop_value(&self) -> IdentAndType {
         IdentAndType {
             name: self.name.clone(),
@@ -146,97 +432,1347 @@ impl Parse for IdentOrDerivative {
     }
 }
 
+/// The temporary holding the next-step value of `var`. It is hygienic so
+/// that it cannot clash with anything the user names.
+pub fn next_value_ident(var: &Ident) -> Ident {
+    Ident::new(
+        &format!("next_{}", var),
+        var.span().resolved_at(Span::mixed_site()),
+    )
+}
+
+/// Rewrites every `~x` in `tokens` into the temporary holding the next-step
+/// value of `x`, recording each `x` in `refs`.
+fn replace_next_refs(tokens: TokenStream, refs: &mut Vec<Ident>) -> TokenStream {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut replaced = TokenStream::new();
+    let mut i = 0;
+    while i < toks.len() {
+        match &toks[i] {
+            TokenTree::Group(group) => {
+                let mut new_group =
+                    Group::new(group.delimiter(), replace_next_refs(group.stream(), refs));
+                new_group.set_span(group.span());
+                replaced.append(new_group);
+            }
+            TokenTree::Punct(tilde) if tilde.as_char() == '~' && i + 1 < toks.len() => {
+                if let TokenTree::Ident(var) = &toks[i + 1] {
+                    replaced.append(next_value_ident(var));
+                    refs.push(var.clone());
+                    i += 2;
+                    continue;
+                }
+                replaced.append(toks[i].clone());
+            }
+            tok => replaced.append(tok.clone()),
+        }
+        i += 1;
+    }
+    replaced
+}
+
+/// Whether `ident` is a noise term: `xi`, or `xi_<name>` to tell apart
+/// independent noise terms.
+fn is_noise(ident: &Ident) -> bool {
+    let name = ident.to_string();
+    name == "xi" || name.starts_with("xi_")
+}
+
+/// The local holding this step's sample of the noise term `xi`.
+fn noise_ident(xi: &Ident) -> Ident {
+    Ident::new(
+        &format!("noise_{}", xi),
+        xi.span().resolved_at(Span::mixed_site()),
+    )
+}
+
+/// Rewrites every noise term in `tokens` (but not fields, as in `self.xi`)
+/// into the local holding its sample, recording each in `refs`.
+fn replace_noise(tokens: TokenStream, refs: &mut Vec<Ident>) -> TokenStream {
+    let mut replaced = TokenStream::new();
+    let mut after_dot = false;
+    for tok in tokens {
+        let is_dot = matches!(&tok, TokenTree::Punct(p) if p.as_char() == '.');
+        match tok {
+            TokenTree::Group(group) => {
+                let mut new_group =
+                    Group::new(group.delimiter(), replace_noise(group.stream(), refs));
+                new_group.set_span(group.span());
+                replaced.append(new_group);
+            }
+            TokenTree::Ident(xi) if !after_dot && is_noise(&xi) => {
+                replaced.append(noise_ident(&xi));
+                refs.push(xi);
+            }
+            tok => replaced.append(tok),
+        }
+        after_dot = is_dot;
+    }
+    replaced
+}
+
+/// The field of a neuron group holding the number of neurons.
+pub fn size_field() -> Ident {
+    Ident::new("size", Span::call_site())
+}
+
+/// The field holding the `Noise` that a neuron with noise terms samples.
+pub fn noise_field() -> Ident {
+    Ident::new("noise", Span::call_site())
+}
+
+/// The distinct noise terms of the equations, in the order they appear.
+pub fn noise_terms(equations: &[Equation]) -> Vec<Ident> {
+    let mut terms: Vec<Ident> = Vec::new();
+    for xi in equations.iter().flat_map(|e| e.noise_refs.iter()) {
+        if !terms.contains(xi) {
+            terms.push(xi.clone());
+        }
+    }
+    terms
+}
+
+/// Checks that noise terms only appear in derivative equations integrated
+/// with Euler, which is then the Euler-Maruyama method.
+fn check_noise(equations: &[Equation], method: Method) -> Result<()> {
+    for equation in equations {
+        if let Some(xi) = equation.noise_refs.first() {
+            if !equation.is_derivative() {
+                return Err(Error::new(
+                    xi.span(),
+                    format!("`{}` can only be used in derivative equations", xi),
+                ));
+            }
+            if method != Method::Euler {
+                return Err(Error::new(
+                    xi.span(),
+                    format!(
+                        "`{}` needs the `euler` method (Euler-Maruyama), not `{}`",
+                        xi,
+                        method.name()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub struct Equation {
     left_side: IdentOrDerivative,
     right_side: Expr,
+    // The right-hand side before expressions are inlined into it, to
+    // describe the model as it was written.
+    written: Expr,
+    next_refs: Vec<Ident>,
+    noise_refs: Vec<Ident>,
+    unless_refractory: bool,
+}
+
+/// Whether the token is the `(unless refractory)` flag of an equation.
+fn is_unless_refractory(tok: &TokenTree) -> bool {
+    match tok {
+        TokenTree::Group(group) if group.delimiter() == Delimiter::Parenthesis => {
+            let words: Vec<String> = group.stream().into_iter().map(|t| t.to_string()).collect();
+            words == ["unless", "refractory"]
+        }
+        _ => false,
+    }
 }
 
 impl Parse for Equation {
     fn parse(input: ParseStream) -> Result<Self> {
         let lhs: IdentOrDerivative = input.parse()?;
         input.parse::<Token![=]>()?;
-        let rhs: Expr = input.parse()?;
+        // `~x` is not a Rust expression, so it is swapped out before parsing.
+        let mut rhs_toks: Vec<TokenTree> = Vec::new();
+        while !input.is_empty() && !input.peek(Token![;]) {
+            rhs_toks.push(input.parse::<TokenTree>()?);
+        }
+        // As in Brian, `(unless refractory)` after an equation holds its
+        // variable during the refractory period.
+        let unless_refractory =
+            rhs_toks.len() > 1 && is_unless_refractory(&rhs_toks[rhs_toks.len() - 1]);
+        if unless_refractory {
+            rhs_toks.pop();
+        }
+        let (mut next_refs, mut noise_refs) = (Vec::new(), Vec::new());
+        let rhs: Expr = syn::parse2(replace_noise(
+            replace_next_refs(rhs_toks.into_iter().collect(), &mut next_refs),
+            &mut noise_refs,
+        ))?;
         Ok(Equation {
             left_side: lhs,
+            written: rhs.clone(),
             right_side: rhs,
+            next_refs,
+            noise_refs,
+            unless_refractory,
         })
     }
 }
 
-impl ToTokens for Equation {
-    fn to_tokens(&self, tokens: &mut TokenStream) {
-        let lhs = match &self.left_side {
-            IdentOrDerivative::Derivative(i) => {
-                i
-            }
+impl Equation {
+    fn is_derivative(&self) -> bool {
+        match self.left_side {
+            IdentOrDerivative::Derivative(_) => true,
+            IdentOrDerivative::Ident(_) => false,
+        }
+    }
+
+    pub fn variable(&self) -> &Ident {
+        match &self.left_side {
+            IdentOrDerivative::Derivative(i) => i,
             IdentOrDerivative::Ident(i) => i,
+        }
+    }
+
+    /// The equation as LaTeX, with `~x` as `\tilde{x}`.
+    pub fn latex(&self) -> String {
+        let var = latex_ident(&self.variable().to_string());
+        let renames: Vec<(Ident, String)> = self
+            .next_refs
+            .iter()
+            .map(|r| (next_value_ident(r), format!("\\tilde{{{}}}", latex_ident(&r.to_string()))))
+            .chain(
+                self.noise_refs
+                    .iter()
+                    .map(|xi| (noise_ident(xi), latex_ident(&xi.to_string()))),
+            )
+            .collect();
+        let rhs = Renderer { renames: &renames }.expr(&self.written);
+        let equation = if self.is_derivative() {
+            format!("\\frac{{d{{{}}}}}{{dt}} = {}", var, rhs)
+        } else {
+            format!("{} \\leftarrow {}", var, rhs)
+        };
+        if self.unless_refractory {
+            format!("{} \\quad \\text{{(unless refractory)}}", equation)
+        } else {
+            equation
+        }
+    }
+
+    /// The right-hand side, bound to a local of the type it must have: that
+    /// of the variable, or that of the variable over time for a derivative.
+    /// The binding is spanned to the right-hand side, so that a mismatch is
+    /// reported there, and the value is only used once it is checked, so
+    /// that the mismatch causes no other errors.
+    fn checked_right_side(&self, fields: &[IdentAndType], time_type: &Type) -> TokenStream {
+        let rhs = &self.right_side;
+        let typ = match fields.iter().find(|f| &f.name == self.variable()) {
+            Some(field) => &field.type_name,
+            None => return quote!((#rhs)),
+        };
+        let value = Ident::new("value", Span::mixed_site());
+        let check = if self.is_derivative() {
+            quote_spanned!(rhs.span()=> let #value: <#typ as std::ops::Div<#time_type>>::Output = #rhs;)
+        } else {
+            quote_spanned!(rhs.span()=> let #value: #typ = #rhs;)
+        };
+        quote!(({ #check #value }))
+    }
+
+    /// The value of the variable in the next step, computed from the current
+    /// state (and any `~x` computed before it).
+    fn next_value(&self, fields: &[IdentAndType], time_type: &Type) -> TokenStream {
+        let var = self.variable();
+        let rhs = self.checked_right_side(fields, time_type);
+        let value = match self.left_side {
+            IdentOrDerivative::Derivative(_) => quote!(#rhs * dt + self.#var),
+            IdentOrDerivative::Ident(_) => rhs,
         };
-        tokens.append(proc_macro2::Ident::new("self", lhs.span()));
-        tokens.append(Punct::new('.', Spacing::Joint));
-        lhs.to_tokens(tokens);
-        tokens.append(Punct::new('=', Spacing::Alone));
-
-	let mut eqn_toks = TokenStream::new();
-	self.right_side.to_tokens(&mut eqn_toks);
-	tokens.append(proc_macro2::Group::new(
-            proc_macro2::Delimiter::Parenthesis,
-            eqn_toks,
+        if self.unless_refractory {
+            let refractory = refractory_ident();
+            quote!(if #refractory { self.#var } else { #value })
+        } else {
+            value
+        }
+    }
+
+    /// The right-hand side of a derivative equation, which is zero during
+    /// the refractory period for `(unless refractory)` equations.
+    fn derivative(&self, fields: &[IdentAndType], typ: &NeuronType) -> TokenStream {
+        let rhs = self.checked_right_side(fields, &typ.time_type);
+        if self.unless_refractory {
+            let (zero, one) = (
+                float_constant(0.0, &typ.float_type),
+                float_constant(1.0, &typ.float_type),
+            );
+            let refractory = refractory_ident();
+            quote!((#rhs * if #refractory { #zero } else { #one }))
+        } else {
+            rhs
+        }
+    }
+}
+
+/// The hidden field counting down the rest of a neuron's refractory period,
+/// or `None` if it is not refractory.
+pub fn refractory_field() -> Ident {
+    Ident::new("refractory_left", Span::call_site())
+}
+
+/// The local that is true during a step taken while the neuron is
+/// refractory.
+fn refractory_ident() -> Ident {
+    Ident::new("refractory", Span::mixed_site())
+}
+
+/// An absolute refractory period: after a spike, `spike_when` is ignored and
+/// the `(unless refractory)` equations are held for `duration`.
+pub struct Refractory {
+    duration: Expr,
+}
+
+impl Refractory {
+    pub fn field(&self, time_type: &Type) -> IdentAndType {
+        IdentAndType {
+            name: refractory_field(),
+            type_name: parse_quote!(Option<#time_type>),
+        }
+    }
+
+    /// Starts the refractory period, right after a reset.
+    pub fn start(&self) -> TokenStream {
+        let (field, duration) = (refractory_field(), &self.duration);
+        quote!(self.#field = Some(#duration);)
+    }
+
+    /// Binds whether the neuron is refractory during the coming step.
+    pub fn begin_step(&self) -> TokenStream {
+        let (field, refractory) = (refractory_field(), refractory_ident());
+        quote!(let #refractory = self.#field.is_some();)
+    }
+
+    /// Counts `dt` off the refractory period, ending it once what is left is
+    /// negligible: small enough, relative to `dt`, to be rounding error.
+    pub fn end_step(&self, float: &Type) -> TokenStream {
+        let (field, left) = (refractory_field(), Ident::new("left", Span::mixed_site()));
+        // `Float::STEP_TOLERANCE`, written out for the primitive floats.
+        let tolerance = match float.to_token_stream().to_string().as_str() {
+            "f64" => quote!(1e-9),
+            "f32" => quote!(1e-3f32),
+            _ => quote!(<#float as ::brian_rs::units::Float>::STEP_TOLERANCE),
+        };
+        quote! {
+            if let Some(#left) = self.#field {
+                self.#field = if #left - dt > dt * #tolerance { Some(#left - dt) } else { None };
+            }
+        }
+    }
+}
+
+/// Parses the optional `refractory { <duration> }` section.
+fn parse_refractory(input: &ParseStream) -> Result<Option<Refractory>> {
+    let has_refractory = input
+        .fork()
+        .parse::<Ident>()
+        .map(|id| id == "refractory")
+        .unwrap_or(false);
+    if !has_refractory {
+        return Ok(None);
+    }
+    expect_str(input, "refractory")?;
+    let duration_toks;
+    braced!(duration_toks in input);
+    Ok(Some(Refractory {
+        duration: duration_toks.parse()?,
+    }))
+}
+
+/// Checks that every equation assigns a state variable (declared in
+/// `initialize`, not in `params` or `expressions`) and that no variable is
+/// assigned twice.
+fn check_assignments(
+    equations: &[Equation],
+    params: &[IdentAndType],
+    inits: &[IdentAndValue],
+    expressions: &[IdentAndValue],
+) -> Result<()> {
+    for (idx, equation) in equations.iter().enumerate() {
+        let var = equation.variable();
+        if expressions.iter().any(|e| &e.name == var) {
+            return Err(Error::new(
+                var.span(),
+                format!("`{}` is an expression, so it cannot be assigned", var),
+            ));
+        }
+        if params.iter().any(|p| &p.name == var) {
+            return Err(Error::new(
+                var.span(),
+                format!(
+                    "`{}` is a parameter, so it cannot be assigned; declare it in `initialize` instead",
+                    var
+                ),
+            ));
+        }
+        if !inits.iter().any(|i| &i.name == var) {
+            return Err(Error::new(
+                var.span(),
+                format!("`{}` is not a state variable; declare it in `initialize`", var),
+            ));
+        }
+        if equations[..idx].iter().any(|e| e.variable() == var) {
+            return Err(Error::new(
+                var.span(),
+                format!("`{}` is assigned more than once", var),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every `~x` comes after an equation for `x`. Only Euler
+/// integrates one equation at a time, so the other methods cannot have `~x`
+/// in derivative equations.
+fn check_next_refs(equations: &[Equation], method: Method) -> Result<()> {
+    for (idx, equation) in equations.iter().enumerate() {
+        for var in equation.next_refs.iter() {
+            if !equations[..idx].iter().any(|e| e.variable() == var) {
+                return Err(Error::new(
+                    var.span(),
+                    format!("`~{}` is only defined after an equation for `{}`", var, var),
+                ));
+            }
+            if method != Method::Euler && equation.is_derivative() {
+                return Err(Error::new(
+                    var.span(),
+                    format!(
+                        "`~{}` cannot be used in a derivative equation with the `{}` method",
+                        var,
+                        method.name()
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `tokens` read any of the given fields of `self`.
+fn reads_fields(tokens: TokenStream, fields: &[Ident]) -> bool {
+    let found = Cell::new(false);
+    map_self_fields(tokens, fields, &|field| {
+        found.set(true);
+        quote!(self.#field)
+    });
+    found.get()
+}
+
+/// The field `x` if `expr` is `self.x`.
+fn self_field(expr: &Expr) -> Option<&Ident> {
+    match expr {
+        Expr::Field(field) => match (&*field.base, &field.member) {
+            (Expr::Path(base), Member::Named(name)) if base.path.is_ident("self") => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Splits the right-hand side of a derivative equation into terms that are
+/// each either free of the `state` variables or one of them times a factor
+/// that is free of them, for exact integration. Anything else that reads
+/// the state is not linear, and is reported where it reads it.
+fn linear_terms(expr: &Expr, state: &[Ident]) -> Result<Vec<LinearTerm>> {
+    if !reads_fields(expr.to_token_stream(), state) {
+        return Ok(vec![LinearTerm {
+            var: None,
+            value: expr.to_token_stream(),
+        }]);
+    }
+    if let Some(var) = self_field(expr) {
+        return Ok(vec![LinearTerm {
+            var: state.iter().position(|s| s == var),
+            value: unit_ident(var).to_token_stream(),
+        }]);
+    }
+    let map = |terms: Vec<LinearTerm>, f: &dyn Fn(TokenStream) -> TokenStream| {
+        terms
+            .into_iter()
+            .map(|t| LinearTerm {
+                var: t.var,
+                value: f(t.value),
+            })
+            .collect()
+    };
+    let nonlinear = || {
+        Error::new_spanned(
+            expr,
+            "This is not linear in the variables with derivatives, so the `exact` method cannot integrate it",
+        )
+    };
+    match expr {
+        Expr::Paren(paren) => linear_terms(&paren.expr, state),
+        Expr::Group(group) => linear_terms(&group.expr, state),
+        Expr::Unary(unary) if matches!(unary.op, UnOp::Neg(_)) => {
+            Ok(map(linear_terms(&unary.expr, state)?, &|v| quote!(-(#v))))
+        }
+        Expr::Binary(binary) => {
+            let (left, right) = (&*binary.left, &*binary.right);
+            match binary.op {
+                BinOp::Add(_) | BinOp::Sub(_) => {
+                    let mut terms = linear_terms(left, state)?;
+                    let right_terms = linear_terms(right, state)?;
+                    if let BinOp::Sub(_) = binary.op {
+                        terms.extend(map(right_terms, &|v| quote!(-(#v))));
+                    } else {
+                        terms.extend(right_terms);
+                    }
+                    Ok(terms)
+                }
+                BinOp::Mul(_) if !reads_fields(left.to_token_stream(), state) => {
+                    Ok(map(linear_terms(right, state)?, &|v| quote!((#left) * (#v))))
+                }
+                BinOp::Mul(_) | BinOp::Div(_) if !reads_fields(right.to_token_stream(), state) => {
+                    let op = &binary.op;
+                    Ok(map(linear_terms(left, state)?, &|v| quote!((#v) #op (#right))))
+                }
+                _ => Err(nonlinear()),
+            }
+        }
+        _ => Err(nonlinear()),
+    }
+}
+
+/// Checks that the derivative equations of a model integrated with the
+/// `exact` method are linear in the variables with derivatives, and that the
+/// field caching its propagator is not declared.
+fn check_linear(
+    equations: &[Equation],
+    method: Method,
+    params: &[IdentAndType],
+    inits: &[IdentAndValue],
+) -> Result<()> {
+    if method != Method::Exact {
+        return Ok(());
+    }
+    let state: Vec<Ident> = equations
+        .iter()
+        .filter(|e| e.is_derivative())
+        .map(|e| e.variable().clone())
+        .collect();
+    for equation in equations.iter().filter(|e| e.is_derivative()) {
+        linear_terms(&equation.right_side, &state)?;
+    }
+    check_reserved(
+        &propagator_field(),
+        params,
+        inits,
+        "`propagator` holds the propagator of the `exact` method, so it cannot be declared",
+    )
+}
+
+/// The hidden field caching the propagator of a model integrated with the
+/// `exact` method, if it has derivative equations.
+pub fn propagator(equations: &[Equation], method: Method) -> Option<IdentAndType> {
+    let count = equations.iter().filter(|e| e.is_derivative()).count();
+    if method != Method::Exact || count == 0 {
+        return None;
+    }
+    Some(IdentAndType {
+        name: propagator_field(),
+        type_name: propagator_type(count),
+    })
+}
+
+/// The variables with derivative equations, their types and the right-hand
+/// sides of their equations, reading the state through `stage_ident`.
+fn derivative_system(
+    equations: &[Equation],
+    fields: &[IdentAndType],
+    typ: &NeuronType,
+) -> Result<(Vec<Ident>, Vec<Type>, Vec<TokenStream>)> {
+    let derivatives: Vec<&Equation> = equations.iter().filter(|e| e.is_derivative()).collect();
+    let state: Vec<Ident> = derivatives.iter().map(|e| e.variable().clone()).collect();
+    let mut types = Vec::new();
+    for var in state.iter() {
+        match fields.iter().find(|f| &f.name == var) {
+            Some(field) => types.push(field.type_name.clone()),
+            None => {
+                return Err(Error::new(
+                    var.span(),
+                    format!("`{}` needs to be a field to have a derivative", var),
+                ))
+            }
+        }
+    }
+    let rhs: Vec<TokenStream> = derivatives
+        .iter()
+        .map(|e| {
+            map_self_fields(e.derivative(fields, typ), &state, &|var| {
+                stage_ident(var).to_token_stream()
+            })
+        })
+        .collect();
+    Ok((state, types, rhs))
+}
+
+/// The variables the equations assign, each once.
+fn assigned_vars(equations: &[Equation]) -> Vec<Ident> {
+    let mut assigned: Vec<Ident> = Vec::new();
+    for equation in equations {
+        if !assigned.contains(equation.variable()) {
+            assigned.push(equation.variable().clone());
+        }
+    }
+    assigned
+}
+
+/// Once the next values of the derivative variables are bound, evaluates the
+/// other equations and assigns every next value. Later equations for the
+/// same variable win.
+fn finish_update(equations: &[Equation], fields: &[IdentAndType], time_type: &Type) -> TokenStream {
+    let mut updates = TokenStream::new();
+    for equation in equations.iter().filter(|e| !e.is_derivative()) {
+        let temp = next_value_ident(equation.variable());
+        let value = equation.next_value(fields, time_type);
+        updates.extend(quote!(let #temp = #value;));
+    }
+    for var in assigned_vars(equations) {
+        let temp = next_value_ident(&var);
+        updates.extend(quote!(self.#var = #temp;));
+    }
+    updates
+}
+
+/// Updates the state from the equations all at once: every right-hand side
+/// is evaluated into a temporary from the old state, and only then are the
+/// temporaries assigned.
+///
+/// With Euler, the equations are evaluated in order so that they can use the
+/// `~x` of earlier ones. Otherwise the derivative equations are integrated
+/// together first and the other equations are evaluated after them.
+pub fn simultaneous_update(
+    equations: &[Equation],
+    method: Method,
+    fields: &[IdentAndType],
+    typ: &NeuronType,
+) -> Result<TokenStream> {
+    let (time_type, float) = (&typ.time_type, &typ.float_type);
+    let mut updates = TokenStream::new();
+    if method == Method::Euler {
+        // One sample of each noise term per step, shared by the equations
+        // that use it.
+        let noise = noise_field();
+        for xi in noise_terms(equations) {
+            let sample = noise_ident(&xi);
+            updates.extend(quote!(let #sample: f64 = self.#noise.xi(dt);));
+        }
+        for equation in equations {
+            let temp = next_value_ident(equation.variable());
+            let value = equation.next_value(fields, time_type);
+            updates.extend(quote!(let #temp = #value;));
+        }
+        for var in assigned_vars(equations) {
+            let temp = next_value_ident(&var);
+            updates.extend(quote!(self.#var = #temp;));
+        }
+    } else {
+        let (state, types, rhs) = derivative_system(equations, fields, typ)?;
+        if method == Method::Exact {
+            // The step only uses the terms of the equations, so the
+            // equations are checked in a closure that is never called.
+            let checks = equations
+                .iter()
+                .filter(|e| e.is_derivative())
+                .map(|e| e.derivative(fields, typ));
+            updates.extend(quote!(let _ = || { #(let _ = #checks;)* };));
+            let mut rows = Vec::new();
+            for equation in equations.iter().filter(|e| e.is_derivative()) {
+                let hold = if equation.unless_refractory {
+                    Some(refractory_ident().to_token_stream())
+                } else {
+                    None
+                };
+                rows.push((linear_terms(&equation.right_side, &state)?, hold));
+            }
+            updates.extend(exact_step(&state, &types, &rows, &next_value_ident));
+        } else {
+            updates.extend(integrate(method, &state, &types, &rhs, &next_value_ident, float));
+        }
+        updates.extend(finish_update(equations, fields, time_type));
+    }
+    Ok(updates)
+}
+
+/// The `advance_adaptive` method of a neuron, which integrates its
+/// derivative equations with an adaptive Dormand-Prince driver. Neurons with
+/// `~x` in a derivative equation need their equations integrated one at a
+/// time, neurons with noise need Euler-Maruyama, and the driver works in
+/// `f64`, so none of them get the method.
+pub fn adaptive_driver(
+    equations: &[Equation],
+    fields: &[IdentAndType],
+    on_spike: &TokenStream,
+    refractory: Option<&Refractory>,
+    input_type: &Type,
+    neuron_type: &NeuronType,
+) -> Result<TokenStream> {
+    if equations
+        .iter()
+        .any(|e| e.is_derivative() && !e.next_refs.is_empty())
+        || !noise_terms(equations).is_empty()
+        || !neuron_type.is_plain()
+    {
+        return Ok(TokenStream::new());
+    }
+    let (time_type, float) = (&neuron_type.time_type, &neuron_type.float_type);
+    let (state, types, rhs) = derivative_system(equations, fields, neuron_type)?;
+    let error = Ident::new("error", Span::mixed_site());
+    let step = dormand_prince(&state, &types, &rhs, &next_value_ident, &error);
+    let finish = finish_update(equations, fields, time_type);
+    let assigned = assigned_vars(equations);
+    let old: Vec<Ident> = assigned
+        .iter()
+        .map(|v| Ident::new(&format!("old_{}", v), Span::mixed_site()))
+        .collect();
+    let scales: Vec<Ident> = state.iter().map(scale_ident).collect();
+    let ratio = Ident::new("ratio", Span::mixed_site());
+    // A step never goes past the end of the refractory period.
+    let (field, left, old_refractory) = (
+        refractory_field(),
+        Ident::new("left", Span::mixed_site()),
+        Ident::new("old_refractory", Span::mixed_site()),
+    );
+    let (limit_step, save_refractory, count_down, restore_refractory) = match refractory {
+        Some(refractory) => (
+            Some(refractory.begin_step().into_iter().chain(quote! {
+                if let Some(#left) = self.#field {
+                    if #left < dt {
+                        dt = #left;
+                        last = false;
+                    }
+                }
+            }).collect::<TokenStream>()),
+            Some(quote!(let #old_refractory = self.#field;)),
+            Some(refractory.end_step(float)),
+            Some(quote!(self.#field = #old_refractory;)),
+        ),
+        None => (None, None, None, None),
+    };
+    let last_mut = refractory.map(|_| quote!(mut));
+    Ok(quote! {
+        /// Advances the neuron by `duration` with a constant `input`, with
+        /// the Dormand-Prince method and steps chosen to keep the relative
+        /// error of each step under `tolerance`. A step that ends in a spike
+        /// is halved until it is no longer than `spike_resolution`, so that
+        /// spike times are found to within it, or to within a millionth of
+        /// a millionth of `duration`. The neuron is reset right after every
+        /// spike.
+        ///
+        /// Returns the times of the spikes since the start of the call.
+        /// Panics if `tolerance` or `spike_resolution` is not positive.
+        pub fn advance_adaptive(
+            &mut self,
+            input: #input_type,
+            duration: #time_type,
+            tolerance: f64,
+            spike_resolution: #time_type,
+        ) -> Vec<#time_type> {
+            assert!(tolerance > 0.0, "The tolerance must be positive");
+            assert!(
+                spike_resolution > duration * 0.0,
+                "The spike resolution must be positive"
+            );
+            if self.did_spike() { #on_spike }
+            let mut spikes = Vec::new();
+            let mut time = duration * 0.0;
+            let mut dt = duration * 0.01;
+            let min_step = duration * 1e-12;
+            #(let mut #scales = self.#state;)*
+            while time < duration {
+                let #last_mut last = duration - time <= dt;
+                if last {
+                    dt = duration - time;
+                }
+                #limit_step
+                #step
+                if #error > tolerance && dt > min_step {
+                    dt *= (0.9 * (tolerance / #error).powf(0.2)).max(0.2);
+                    continue;
+                }
+                #(let #old = self.#assigned;)*
+                #save_refractory
+                #finish
+                #count_down
+                if self.did_spike() && dt > spike_resolution && dt > min_step {
+                    #(self.#assigned = #old;)*
+                    #restore_refractory
+                    dt *= 0.5;
+                    continue;
+                }
+                time = if last { duration } else { time + dt };
+                if self.did_spike() {
+                    spikes.push(time);
+                    #on_spike
+                }
+                #(
+                    let #ratio: &f64 = &(self.#state / #scales);
+                    if #scales * 0.0 == #scales || #ratio.abs() > 1.0 {
+                        #scales = self.#state;
+                    }
+                )*
+                dt *= if #error == 0.0 {
+                    5.0
+                } else {
+                    (0.9 * (tolerance / #error).powf(0.2)).clamp(0.2, 5.0)
+                };
+            }
+            spikes
+        }
+    })
+}
+
+/// The hidden field of a neuron or synapse with type parameters, which
+/// holds them in case no other field uses them.
+fn phantom_field() -> Ident {
+    Ident::new("phantom", Span::call_site())
+}
+
+/// Checks that a field that the macro adds is not declared too.
+fn check_reserved(
+    field: &Ident,
+    params: &[IdentAndType],
+    inits: &[IdentAndValue],
+    message: &str,
+) -> Result<()> {
+    match params
+        .iter()
+        .map(|p| &p.name)
+        .chain(inits.iter().map(|i| &i.name))
+        .find(|name| *name == field)
+    {
+        Some(name) => Err(Error::new(name.span(), message)),
+        None => Ok(()),
+    }
+}
+
+/// Checks that a type with type parameters or a float other than `f64` only
+/// uses what works for it: the methods that need nothing but arithmetic, and
+/// no noise, which is sampled in `f64`.
+fn check_generic(
+    typ: &NeuronType,
+    method: Method,
+    equations: &[Equation],
+    params: &[IdentAndType],
+    inits: &[IdentAndValue],
+) -> Result<()> {
+    if typ.is_plain() {
+        return Ok(());
+    }
+    if !matches!(method, Method::Euler | Method::Rk2 | Method::Rk4) {
+        return Err(Error::new(
+            typ.type_name.span(),
+            format!(
+                "`{}` has type parameters or a float other than `f64`, so it can only use the `euler`, `rk2` or `rk4` method, not `{}`",
+                typ.type_name,
+                method.name()
+            ),
         ));
+    }
+    if let Some(xi) = equations.iter().flat_map(|e| e.noise_refs.iter()).next() {
+        return Err(Error::new(
+            xi.span(),
+            "Noise is sampled in `f64`, so it needs a type without type parameters that is computed in `f64`",
+        ));
+    }
+    if !typ.generics.params.is_empty() {
+        check_reserved(
+            &phantom_field(),
+            params,
+            inits,
+            "`phantom` holds the type parameters, so it cannot be declared",
+        )?;
+    }
+    Ok(())
+}
+
+/// The generics of the impls of a type with type parameters. On top of the
+/// bounds it was declared with, the generated code needs the arithmetic of
+/// its integrator on the time and the derivative variables, that of the
+/// refractory countdown, and the voltage to convert to the other input ports
+/// for `handle_input`.
+pub fn generic_bounds(
+    typ: &NeuronType,
+    equations: &[Equation],
+    fields: &[IdentAndType],
+    refractory: bool,
+    inputs: &[IdentAndType],
+) -> Generics {
+    let mut generics = typ.generics.clone();
+    if generics.params.is_empty() {
+        return generics;
+    }
+    let (voltage, time, float) = (&typ.voltage_type, &typ.time_type, &typ.float_type);
+    let predicates = &mut generics.make_where_clause().predicates;
+    predicates.push(parse_quote! {
+        #time: Copy + std::ops::Mul<#float, Output = #time> + std::ops::Div<#float, Output = #time>
+    });
+    let mut seen: Vec<String> = Vec::new();
+    for equation in equations.iter().filter(|e| e.is_derivative()) {
+        let var_type = match fields.iter().find(|f| &f.name == equation.variable()) {
+            Some(field) => &field.type_name,
+            None => continue,
+        };
+        let key = var_type.to_token_stream().to_string();
+        if seen.contains(&key) {
+            continue;
+        }
+        seen.push(key);
+        let rate = quote!(<#var_type as std::ops::Div<#time>>::Output);
+        predicates.push(parse_quote! {
+            #var_type: Copy + std::ops::Add<Output = #var_type> + std::ops::Div<#time>
+        });
+        predicates.push(parse_quote! {
+            #rate: Copy
+                + std::ops::Add<Output = #rate>
+                + std::ops::Mul<#float, Output = #rate>
+                + std::ops::Mul<#time, Output = #var_type>
+        });
+    }
+    if refractory {
+        predicates.push(parse_quote!(#time: PartialOrd + std::ops::Sub<Output = #time>));
+    }
+    if !inputs.is_empty() {
+        predicates.push(parse_quote!(#voltage: Copy));
+    }
+    for port in inputs.iter().skip(1) {
+        let port_type = &port.type_name;
+        predicates.push(parse_quote!(#voltage: std::ops::Mul<#float, Output = #port_type>));
+    }
+    generics
+}
 
-        if let IdentOrDerivative::Derivative(_) = self.left_side {
-            tokens.append(Punct::new('*', Spacing::Alone));
-            tokens.append(proc_macro2::Ident::new("dt", lhs.span()));
-            tokens.append(Punct::new('+', Spacing::Alone));
-            tokens.append(proc_macro2::Ident::new("self", lhs.span()));
-            tokens.append(Punct::new('.', Spacing::Joint));
-            lhs.to_tokens(tokens);
+/// Parses the optional `inputs { <name>: <type>, ... }` section, which
+/// declares the input ports of a neuron. Synapses feed every port with
+/// voltages, so each port has the voltage type of the neuron.
+fn parse_inputs(input: &ParseStream, voltage_type: &Type) -> Result<Vec<IdentAndType>> {
+    let has_inputs = input
+        .fork()
+        .parse::<Ident>()
+        .map(|id| id == "inputs")
+        .unwrap_or(false);
+    if !has_inputs {
+        return Ok(Vec::new());
+    }
+    expect_str(input, "inputs")?;
+    let ports = get_delimited_within_braces::<IdentAndType, Token![,]>(input)?;
+    for (idx, port) in ports.iter().enumerate() {
+        if ports[..idx].iter().any(|p| p.name == port.name) {
+            return Err(Error::new(
+                port.name.span(),
+                format!("`{}` is already an input", port.name),
+            ));
+        }
+        if port.type_name.to_token_stream().to_string()
+            != voltage_type.to_token_stream().to_string()
+        {
+            return Err(Error::new_spanned(
+                &port.type_name,
+                format!(
+                    "Synapses feed `{}` with voltages, so it needs the voltage type of the neuron",
+                    port.name
+                ),
+            ));
         }
-        tokens.append(Punct::new(';', Spacing::Alone));
     }
+    Ok(ports)
+}
+
+/// The methods that the macros give models and their groups, which an
+/// expression cannot be named after since it gets a method of its name.
+const GENERATED_METHODS: &[&str] = &[
+    "new",
+    "describe",
+    "to_latex",
+    "advance_adaptive",
+    "handle_named_inputs",
+    "handle_inputs",
+    "len",
+    "is_empty",
+    "did_spike",
+    "get_voltage",
+    "handle_input",
+    "handle_port_inputs",
+    "ports",
+    "advance",
+    "advance_once",
+    "current_weight",
+    "on_pre",
+    "on_post",
+    "params",
+    "state_vars",
+    "units",
+    "get_var",
+    "set_var",
+];
+
+/// Parses the optional `expressions { <name>: <type> = <value>; ... }`
+/// section. Each value may use the expressions before it, which are inlined
+/// into it.
+fn parse_expressions(
+    input: &ParseStream,
+    params: &[IdentAndType],
+    inits: &[IdentAndValue],
+) -> Result<Vec<IdentAndValue>> {
+    let has_expressions = input
+        .fork()
+        .parse::<Ident>()
+        .map(|id| id == "expressions")
+        .unwrap_or(false);
+    if !has_expressions {
+        return Ok(Vec::new());
+    }
+    expect_str(input, "expressions")?;
+    let mut expressions: Vec<IdentAndValue> = Vec::new();
+    for mut expression in get_delimited_within_braces::<IdentAndValue, Token![;]>(input)? {
+        let name = &expression.name;
+        if params.iter().any(|p| &p.name == name)
+            || inits.iter().chain(expressions.iter()).any(|i| &i.name == name)
+        {
+            return Err(Error::new(
+                name.span(),
+                format!("`{}` is already declared", name),
+            ));
+        }
+        if GENERATED_METHODS.iter().any(|method| name == method) {
+            return Err(Error::new(
+                name.span(),
+                format!(
+                    "The macros generate a method called `{}`, so it cannot name an expression",
+                    name
+                ),
+            ));
+        }
+        expression.value = inline_expressions(&expression.value, &expressions)?;
+        expressions.push(expression);
+    }
+    Ok(expressions)
+}
+
+/// Replaces every `self.<name>` of an expression in `expr` with its value,
+/// so that integrators that evaluate the equations at intermediate states
+/// evaluate the expressions there too.
+fn inline_expressions(expr: &Expr, expressions: &[IdentAndValue]) -> Result<Expr> {
+    let names: Vec<Ident> = expressions.iter().map(|e| e.name.clone()).collect();
+    syn::parse2(map_self_fields(expr.to_token_stream(), &names, &|name| {
+        let expression = expressions.iter().find(|e| &e.name == name).unwrap();
+        let mut value = Group::new(Delimiter::Parenthesis, expression.value.to_token_stream());
+        value.set_span(name.span());
+        value.into_token_stream()
+    }))
+}
+
+/// The statements of a `reset`, `on_pre` or `on_post` block, with every
+/// expression they read bound to a local first, so that the expressions are
+/// computed once, from the state before the statements change it.
+fn bind_expressions(statements: &[Expr], expressions: &[IdentAndValue]) -> TokenStream {
+    let block: TokenStream = quote!(#(#statements);*;);
+    let read: Vec<&IdentAndValue> = expressions
+        .iter()
+        .filter(|e| reads_fields(block.clone(), std::slice::from_ref(&e.name)))
+        .collect();
+    let local = |name: &Ident| {
+        Ident::new(&name.to_string(), name.span().resolved_at(Span::mixed_site()))
+    };
+    let names: Vec<Ident> = read.iter().map(|e| e.name.clone()).collect();
+    let bound = map_self_fields(block, &names, &|name| local(name).to_token_stream());
+    let (locals, types, values) = (
+        read.iter().map(|e| local(&e.name)),
+        read.iter().map(|e| &e.typ),
+        read.iter().map(|e| &e.value),
+    );
+    quote!(#(let #locals: #types = #values;)* #bound)
+}
+
+/// A getter for each expression, so that monitors can read them. For a
+/// group, `group` holds the index argument and the fields to index by it.
+pub fn expression_getters(
+    expressions: &[IdentAndValue],
+    group: Option<(&Ident, &[Ident])>,
+) -> TokenStream {
+    let mut getters = TokenStream::new();
+    for IdentAndValue { name, typ, value } in expressions {
+        getters.extend(match group {
+            Some((index, fields)) => {
+                let value = index_fields(value.to_token_stream(), fields, index);
+                quote!(pub fn #name(&self, #index: usize) -> #typ { #value })
+            }
+            None => quote!(pub fn #name(&self) -> #typ { #value }),
+        });
+    }
+    getters
+}
+
+/// Asserts that every expression has its declared type, spanned to its
+/// value, as `Equation::checked_right_side` does for the equations.
+pub fn expression_checks(expressions: &[IdentAndValue]) -> TokenStream {
+    let mut checks = TokenStream::new();
+    for IdentAndValue { typ, value, .. } in expressions {
+        checks.extend(quote_spanned!(value.span()=> let _: #typ = #value;));
+    }
+    checks
+}
+
+/// The impl of `Reflect` for types marked `#[reflect]`, which reads and
+/// writes the parameters and state variables by name as `f64` magnitudes.
+/// Generic types also need the variables that use their type parameters to
+/// be `Magnitude`.
+pub fn reflect_impl(
+    typ: &NeuronType,
+    generics: &Generics,
+    params: &[IdentAndType],
+    state: &[IdentAndType],
+) -> TokenStream {
+    if !typ.reflect {
+        return TokenStream::new();
+    }
+    let magnitude = quote!(::brian_rs::reflect::Magnitude);
+    let mut generics = generics.clone();
+    let type_params = typ.type_params();
+    let vars: Vec<&IdentAndType> = params.iter().chain(state).collect();
+    for var in vars.iter() {
+        let var_type = &var.type_name;
+        if type_params
+            .iter()
+            .any(|param| mentions(var_type.to_token_stream(), param))
+        {
+            let predicates = &mut generics.make_where_clause().predicates;
+            predicates.push(parse_quote!(#var_type: #magnitude));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let type_name = &typ.type_name;
+    let param_names = params.iter().map(|p| p.name.to_string());
+    let state_names = state.iter().map(|s| s.name.to_string());
+    let names: Vec<String> = vars.iter().map(|v| v.name.to_string()).collect();
+    let fields: Vec<&Ident> = vars.iter().map(|v| &v.name).collect();
+    let types: Vec<&Type> = vars.iter().map(|v| &v.type_name).collect();
+    let unknown = format!("`{{}}` is not a variable of `{}`", type_name);
+    quote! {
+        impl #impl_generics ::brian_rs::reflect::Reflect for #type_name #ty_generics #where_clause {
+            fn params(&self) -> &'static [&'static str] {
+                &[#(#param_names),*]
+            }
+
+            fn state_vars(&self) -> &'static [&'static str] {
+                &[#(#state_names),*]
+            }
+
+            fn units(&self, name: &str) -> String {
+                match name {
+                    #(#names => <#types as #magnitude>::units(),)*
+                    _ => panic!(#unknown, name),
+                }
+            }
+
+            fn get_var(&self, name: &str) -> f64 {
+                match name {
+                    #(#names => #magnitude::to_magnitude(&self.#fields),)*
+                    _ => panic!(#unknown, name),
+                }
+            }
+
+            fn set_var(&mut self, name: &str, magnitude: f64) {
+                match name {
+                    #(#names => self.#fields = #magnitude::from_magnitude(magnitude),)*
+                    _ => panic!(#unknown, name),
+                }
+            }
+        }
+    }
+}
+
+/// Parses the optional `method { <name> }` clause, which defaults to Euler.
+fn parse_method(input: &ParseStream) -> Result<Method> {
+    let has_method = input
+        .fork()
+        .parse::<Ident>()
+        .map(|id| id == "method")
+        .unwrap_or(false);
+    if !has_method {
+        return Ok(Method::Euler);
+    }
+    expect_str(input, "method")?;
+    let method_toks;
+    braced!(method_toks in input);
+    method_toks.parse()
 }
 
 pub struct NeuronDef {
     pub neuron_type: NeuronType,
     pub param_list: Vec<IdentAndType>,
     pub initialize_list: Vec<IdentAndValue>,
+    pub inputs: Vec<IdentAndType>,
+    pub expressions: Vec<IdentAndValue>,
+    pub method: Method,
     pub time_step: Vec<Equation>,
     pub spike_when: Expr,
     pub voltage_getter: Expr,
-    pub reset: Vec<Expr>,
+    pub reset: TokenStream,
+    pub refractory: Option<Refractory>,
+    pub description: Description,
 }
 
 impl Parse for NeuronDef {
     fn parse(input: ParseStream) -> Result<Self> {
+        NeuronDef::parse_with(input, false)
+    }
+}
+
+/// The definition of an input generator, which is that of a neuron with no
+/// input.
+pub struct InputGeneratorDef(pub NeuronDef);
+
+impl Parse for InputGeneratorDef {
+    fn parse(input: ParseStream) -> Result<Self> {
+        NeuronDef::parse_with(input, true).map(InputGeneratorDef)
+    }
+}
+
+impl NeuronDef {
+    fn parse_with(input: ParseStream, input_generator: bool) -> Result<Self> {
+        let sections = if input_generator {
+            INPUT_GENERATOR_SECTIONS
+        } else {
+            NEURON_SECTIONS
+        };
         let typ: NeuronType = input.parse()?;
+        if input_generator && typ.reflect {
+            return Err(Error::new(
+                typ.type_name.span(),
+                "Input generators do not implement `Reflect`",
+            ));
+        }
         input.parse::<Token![:]>()?;
-        expect_str(&input, "params")?;
+        expect_section(&input, "params", sections)?;
         let params = get_delimited_within_braces::<IdentAndType, Token![,]>(&input)?;
-        expect_str(&input, "initialize")?;
+        expect_section(&input, "initialize", sections)?;
         let inits = get_delimited_within_braces::<IdentAndValue, Token![;]>(&input)?;
-        expect_str(&input, "time_step")?;
-        let time_steps = get_delimited_within_braces::<Equation, Token![;]>(&input)?;
-        expect_str(&input, "spike_when")?;
-        let spike_cond: Expr = {
+        let inputs = if input_generator {
+            Vec::new()
+        } else {
+            parse_inputs(&input, &typ.voltage_type)?
+        };
+        let expressions = parse_expressions(&input, &params, &inits)?;
+        let method = parse_method(&input)?;
+        expect_section(&input, "time_step", sections)?;
+        let mut time_steps = get_delimited_within_braces::<Equation, Token![;]>(&input)?;
+        for equation in time_steps.iter_mut() {
+            equation.right_side = inline_expressions(&equation.right_side, &expressions)?;
+        }
+        check_assignments(&time_steps, &params, &inits, &expressions)?;
+        check_next_refs(&time_steps, method)?;
+        check_noise(&time_steps, method)?;
+        if !noise_terms(&time_steps).is_empty() {
+            check_reserved(
+                &noise_field(),
+                &params,
+                &inits,
+                "`noise` holds the noise of neurons with `xi`, so it cannot be declared",
+            )?;
+        }
+        check_generic(&typ, method, &time_steps, &params, &inits)?;
+        check_linear(&time_steps, method, &params, &inits)?;
+        check_reserved(
+            &size_field(),
+            &params,
+            &inits,
+            "`size` holds the number of neurons of a group, so it cannot be declared",
+        )?;
+        expect_section(&input, "spike_when", sections)?;
+        let written_spike_cond: Expr = {
             let spike_cond_toks;
             braced!(spike_cond_toks in input);
             spike_cond_toks.parse()?
         };
-        expect_str(&input, "get_voltage")?;
-        let voltage_getter: Expr = {
+        let spike_cond = inline_expressions(&written_spike_cond, &expressions)?;
+        expect_section(&input, "get_voltage", sections)?;
+        let written_voltage_getter: Expr = {
             let voltage_toks;
             braced!(voltage_toks in input);
             voltage_toks.parse()?
         };
-        expect_str(&input, "reset")?;
-        let resets = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
+        let voltage_getter = inline_expressions(&written_voltage_getter, &expressions)?;
+        expect_section(&input, "reset", sections)?;
+        let written_resets = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
+        let resets = bind_expressions(&written_resets, &expressions);
+        let refractory = parse_refractory(&input)?;
+        if refractory.is_none() {
+            if let Some(equation) = time_steps.iter().find(|e| e.unless_refractory) {
+                return Err(Error::new(
+                    equation.variable().span(),
+                    "`(unless refractory)` needs a `refractory` section",
+                ));
+            }
+        }
+        let refractory_field = refractory_field();
+        if refractory.is_some() {
+            check_reserved(
+                &refractory_field,
+                &params,
+                &inits,
+                "`refractory_left` holds the rest of the refractory period, so it cannot be declared",
+            )?;
+        }
+        let fields: Vec<&Ident> = params
+            .iter()
+            .map(|p| &p.name)
+            .chain(inits.iter().map(|i| &i.name))
+            .chain(refractory.as_ref().map(|_| &refractory_field))
+            .collect();
+        for equation in time_steps.iter() {
+            check_self_fields(equation.right_side.to_token_stream(), &fields)?;
+        }
+        check_self_fields(spike_cond.to_token_stream(), &fields)?;
+        check_self_fields(voltage_getter.to_token_stream(), &fields)?;
+        check_self_fields(resets.clone(), &fields)?;
+        if input_generator {
+            let mut blocks: Vec<TokenStream> = vec![
+                spike_cond.to_token_stream(),
+                voltage_getter.to_token_stream(),
+            ];
+            blocks.extend(time_steps.iter().map(|e| e.right_side.to_token_stream()));
+            blocks.push(resets.clone());
+            blocks.extend(refractory.iter().map(|r| r.duration.to_token_stream()));
+            blocks.extend(expressions.iter().map(|e| e.value.to_token_stream()));
+            for tokens in blocks {
+                check_no_input(tokens)?;
+            }
+        }
+        let kind = if input_generator {
+            "input generator"
+        } else {
+            "neuron"
+        };
+        let mut description = Description::new(kind, &typ);
+        description.table("Parameters", &params);
+        description.math("Initial values", inits.iter().map(|i| i.latex()).collect());
+        description.table("Inputs", &inputs);
+        description.math("Expressions", expressions.iter().map(|e| e.latex()).collect());
+        description.math("Equations", time_steps.iter().map(|e| e.latex()).collect());
+        description.math("Spikes when", vec![latex_expr(&written_spike_cond)]);
+        description.math("Voltage", vec![latex_expr(&written_voltage_getter)]);
+        description.math("Reset", written_resets.iter().map(latex_expr).collect());
+        description.math(
+            "Refractory period",
+            refractory.iter().map(|r| latex_expr(&r.duration)).collect(),
+        );
         Ok(NeuronDef {
             neuron_type: typ,
             param_list: params,
             initialize_list: inits,
+            inputs,
+            expressions,
+            method,
             time_step: time_steps,
             spike_when: spike_cond,
             voltage_getter: voltage_getter,
             reset: resets,
+            refractory,
+            description,
         })
     }
 }
@@ -245,40 +1781,86 @@ pub struct SynapseDef {
     pub synapse_type: NeuronType,
     pub param_list: Vec<IdentAndType>,
     pub initialize_list: Vec<IdentAndValue>,
+    pub expressions: Vec<IdentAndValue>,
+    pub method: Method,
     pub time_step: Vec<Equation>,
     pub weight_getter: Expr,
-    pub pre_synapse_spike: Vec<Expr>,
-    pub post_synapse_spike: Vec<Expr>,
+    pub pre_synapse_spike: TokenStream,
+    pub post_synapse_spike: TokenStream,
+    pub description: Description,
 }
 
 impl Parse for SynapseDef {
     fn parse(input: ParseStream) -> Result<Self> {
         let typ: NeuronType = input.parse()?;
         input.parse::<Token![:]>()?;
-        expect_str(&input, "params")?;
+        expect_section(&input, "params", SYNAPSE_SECTIONS)?;
         let params = get_delimited_within_braces::<IdentAndType, Token![,]>(&input)?;
-        expect_str(&input, "initialize")?;
+        expect_section(&input, "initialize", SYNAPSE_SECTIONS)?;
         let inits = get_delimited_within_braces::<IdentAndValue, Token![;]>(&input)?;
-        expect_str(&input, "time_step")?;
-        let time_steps = get_delimited_within_braces::<Equation, Token![;]>(&input)?;
-        expect_str(&input, "weight_getter")?;
-        let weight_getter: Expr = {
+        let expressions = parse_expressions(&input, &params, &inits)?;
+        let method = parse_method(&input)?;
+        expect_section(&input, "time_step", SYNAPSE_SECTIONS)?;
+        let mut time_steps = get_delimited_within_braces::<Equation, Token![;]>(&input)?;
+        for equation in time_steps.iter_mut() {
+            equation.right_side = inline_expressions(&equation.right_side, &expressions)?;
+        }
+        check_assignments(&time_steps, &params, &inits, &expressions)?;
+        check_next_refs(&time_steps, method)?;
+        if let Some(equation) = time_steps.iter().find(|e| e.unless_refractory) {
+            return Err(Error::new(
+                equation.variable().span(),
+                "Synapses have no refractory period",
+            ));
+        }
+        if let Some(xi) = time_steps.iter().flat_map(|e| e.noise_refs.iter()).next() {
+            return Err(Error::new(xi.span(), "Synapses cannot have noise"));
+        }
+        check_generic(&typ, method, &time_steps, &params, &inits)?;
+        check_linear(&time_steps, method, &params, &inits)?;
+        expect_section(&input, "weight_getter", SYNAPSE_SECTIONS)?;
+        let written_weight_getter: Expr = {
             let weight_toks;
             braced!(weight_toks in input);
             weight_toks.parse()?
         };
-        expect_str(&input, "on_pre")?;
-        let pre_spike = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
-        expect_str(&input, "on_post")?;
-        let post_spike = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
+        let weight_getter = inline_expressions(&written_weight_getter, &expressions)?;
+        expect_section(&input, "on_pre", SYNAPSE_SECTIONS)?;
+        let written_pre_spike = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
+        let pre_spike = bind_expressions(&written_pre_spike, &expressions);
+        expect_section(&input, "on_post", SYNAPSE_SECTIONS)?;
+        let written_post_spike = get_delimited_within_braces::<Expr, Token![;]>(&input)?;
+        let post_spike = bind_expressions(&written_post_spike, &expressions);
+        let fields: Vec<&Ident> = params
+            .iter()
+            .map(|p| &p.name)
+            .chain(inits.iter().map(|i| &i.name))
+            .collect();
+        for equation in time_steps.iter() {
+            check_self_fields(equation.right_side.to_token_stream(), &fields)?;
+        }
+        check_self_fields(weight_getter.to_token_stream(), &fields)?;
+        check_self_fields(pre_spike.clone(), &fields)?;
+        check_self_fields(post_spike.clone(), &fields)?;
+        let mut description = Description::new("synapse", &typ);
+        description.table("Parameters", &params);
+        description.math("Initial values", inits.iter().map(|i| i.latex()).collect());
+        description.math("Expressions", expressions.iter().map(|e| e.latex()).collect());
+        description.math("Equations", time_steps.iter().map(|e| e.latex()).collect());
+        description.math("Weight", vec![latex_expr(&written_weight_getter)]);
+        description.math("On presynaptic spike", written_pre_spike.iter().map(latex_expr).collect());
+        description.math("On postsynaptic spike", written_post_spike.iter().map(latex_expr).collect());
         Ok(SynapseDef {
             synapse_type: typ,
             param_list: params,
             initialize_list: inits,
+            expressions,
+            method,
             time_step: time_steps,
             weight_getter: weight_getter,
             pre_synapse_spike: pre_spike,
             post_synapse_spike: post_spike,
+            description,
         })
     }
 }
@@ -0,0 +1,687 @@
+//! `define_network!`: populations of neurons joined by projections of
+//! synapses, expanded into a struct with a field for each, so that the types
+//! of every part of the model are checked together.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::parse::{Parse, ParseStream, Result};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{bracketed, parenthesized, parse_quote, Error, Expr, Ident, Token, Type};
+
+use crate::macros::{
+    expect_section, expect_str, get_delimited_within_braces, mentions, IdentAndType,
+};
+
+/// The sections of `define_network!`, in order. Only `populations` is
+/// required.
+const NETWORK_SECTIONS: &[&str] = &["params", "inputs", "populations", "projections", "monitors"];
+
+/// The fields of the network struct that are not named by the user.
+const RESERVED_NAMES: &[&str] = &["dt", "time", "ports"];
+
+/// Whether the next section is `section`, for the optional ones.
+fn peek_section(input: ParseStream, section: &str) -> bool {
+    input
+        .fork()
+        .parse::<Ident>()
+        .map(|id| id == section)
+        .unwrap_or(false)
+}
+
+/// How each neuron or synapse is made: `(args)` for `Type::new(args)`, or
+/// `= value`. Either may use `i` (and, for synapses, `j`).
+enum Make {
+    New(Punctuated<Expr, Token![,]>),
+    Value(Box<Expr>),
+}
+
+impl Parse for Make {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            return Ok(Make::Value(input.parse()?));
+        }
+        let args;
+        parenthesized!(args in input);
+        Ok(Make::New(Punctuated::parse_terminated(&args)?))
+    }
+}
+
+impl Make {
+    fn expand(&self, typ: &Type) -> TokenStream {
+        match self {
+            Make::New(args) => quote!(<#typ>::new(#args)),
+            Make::Value(value) => value.to_token_stream(),
+        }
+    }
+}
+
+/// `<name>: <type>[<size>](<args>)`, or `<name>: <type>[<size>] = <value>`.
+struct Population {
+    name: Ident,
+    typ: Type,
+    size: Expr,
+    make: Make,
+}
+
+impl Parse for Population {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name: Ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let typ: Type = input.parse()?;
+        let size_toks;
+        bracketed!(size_toks in input);
+        let size: Expr = size_toks.parse()?;
+        let make: Make = input.parse()?;
+        Ok(Population {
+            name,
+            typ,
+            size,
+            make,
+        })
+    }
+}
+
+/// `<name>: <type>[<pre> -> <post>, <option>: <value>, ...](<args>)`, or
+/// with `= <value>` in place of the arguments. The options are `rule`,
+/// `seed`, `delay` and `port`.
+struct Projection {
+    name: Ident,
+    typ: Type,
+    from: Ident,
+    to: Ident,
+    rule: Option<Expr>,
+    seed: Option<Expr>,
+    delay: Option<Expr>,
+    port: Option<Ident>,
+    make: Make,
+}
+
+impl Parse for Projection {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let name: Ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let typ: Type = input.parse()?;
+        let options;
+        bracketed!(options in input);
+        let from: Ident = options.parse()?;
+        options.parse::<Token![->]>()?;
+        let to: Ident = options.parse()?;
+        let (mut rule, mut seed, mut delay, mut port) = (None, None, None, None);
+        while !options.is_empty() {
+            options.parse::<Token![,]>()?;
+            let option: Ident = options.parse()?;
+            options.parse::<Token![:]>()?;
+            let already = match option.to_string().as_str() {
+                "rule" => rule.replace(options.parse::<Expr>()?).is_some(),
+                "seed" => seed.replace(options.parse::<Expr>()?).is_some(),
+                "delay" => delay.replace(options.parse::<Expr>()?).is_some(),
+                "port" => port.replace(options.parse::<Ident>()?).is_some(),
+                _ => return Err(Error::new(
+                    option.span(),
+                    format!(
+                        "Unknown option `{}`; the options are `rule`, `seed`, `delay` and `port`",
+                        option
+                    ),
+                )),
+            };
+            if already {
+                return Err(Error::new(
+                    option.span(),
+                    format!("`{}` is already set", option),
+                ));
+            }
+        }
+        let make: Make = input.parse()?;
+        Ok(Projection {
+            name,
+            typ,
+            from,
+            to,
+            rule,
+            seed,
+            delay,
+            port,
+            make,
+        })
+    }
+}
+
+enum MonitorKind {
+    Spikes,
+    Voltages,
+}
+
+/// `spikes(<population>)` or `voltages(<population>)`.
+struct Monitor {
+    kind: MonitorKind,
+    population: Ident,
+}
+
+impl Parse for Monitor {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let kind: Ident = input.parse()?;
+        let kind = match kind.to_string().as_str() {
+            "spikes" => MonitorKind::Spikes,
+            "voltages" => MonitorKind::Voltages,
+            _ => return Err(Error::new(kind.span(), "Expected `spikes` or `voltages`")),
+        };
+        let population_toks;
+        parenthesized!(population_toks in input);
+        Ok(Monitor {
+            kind,
+            population: population_toks.parse()?,
+        })
+    }
+}
+
+impl Monitor {
+    /// The field that holds the recording, and the method that returns it.
+    fn field(&self) -> Ident {
+        match self.kind {
+            MonitorKind::Spikes => format_ident!("{}_spikes", self.population),
+            MonitorKind::Voltages => format_ident!("{}_voltages", self.population),
+        }
+    }
+}
+
+pub struct NetworkDef {
+    type_name: Ident,
+    voltage_type: Type,
+    time_type: Type,
+    params: Vec<IdentAndType>,
+    inputs: Vec<Population>,
+    populations: Vec<Population>,
+    projections: Vec<Projection>,
+    monitors: Vec<Monitor>,
+}
+
+impl Parse for NetworkDef {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let type_name: Ident = input.parse()?;
+        input.parse::<Token![<]>()?;
+        let voltage_type: Type = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let time_type: Type = input.parse()?;
+        input.parse::<Token![>]>()?;
+        input.parse::<Token![:]>()?;
+        let params = if peek_section(input, "params") {
+            expect_str(&input, "params")?;
+            get_delimited_within_braces::<IdentAndType, Token![,]>(&input)?
+        } else {
+            Vec::new()
+        };
+        let inputs = if peek_section(input, "inputs") {
+            expect_str(&input, "inputs")?;
+            get_delimited_within_braces::<Population, Token![,]>(&input)?
+        } else {
+            Vec::new()
+        };
+        expect_section(&input, "populations", NETWORK_SECTIONS)?;
+        let populations = get_delimited_within_braces::<Population, Token![,]>(&input)?;
+        let projections = if peek_section(input, "projections") {
+            expect_str(&input, "projections")?;
+            get_delimited_within_braces::<Projection, Token![,]>(&input)?
+        } else {
+            Vec::new()
+        };
+        let monitors = if peek_section(input, "monitors") {
+            expect_str(&input, "monitors")?;
+            get_delimited_within_braces::<Monitor, Token![,]>(&input)?
+        } else {
+            Vec::new()
+        };
+        if !input.is_empty() {
+            expect_section(&input, "monitors", NETWORK_SECTIONS)?;
+        }
+
+        // Every population, projection and monitor is a field of the struct.
+        let mut names: Vec<Ident> = Vec::new();
+        let fields = inputs
+            .iter()
+            .chain(populations.iter())
+            .map(|p| p.name.clone())
+            .chain(projections.iter().map(|p| p.name.clone()))
+            .chain(monitors.iter().map(|m| {
+                let mut field = m.field();
+                field.set_span(m.population.span());
+                field
+            }));
+        for name in fields {
+            if RESERVED_NAMES.iter().any(|r| name == r) {
+                return Err(Error::new(
+                    name.span(),
+                    format!(
+                        "`{}` is a field of every network, so it cannot be declared",
+                        name
+                    ),
+                ));
+            }
+            if names.contains(&name) {
+                return Err(Error::new(
+                    name.span(),
+                    format!("`{}` is already defined", name),
+                ));
+            }
+            names.push(name);
+        }
+        let is_input = |name: &Ident| inputs.iter().any(|p| &p.name == name);
+        let is_population = |name: &Ident| populations.iter().any(|p| &p.name == name);
+        let not_a_population =
+            |name: &Ident| Error::new(name.span(), format!("`{}` is not a population", name));
+        for projection in projections.iter() {
+            if !is_input(&projection.from) && !is_population(&projection.from) {
+                return Err(not_a_population(&projection.from));
+            }
+            if is_input(&projection.to) {
+                return Err(Error::new(
+                    projection.to.span(),
+                    format!(
+                        "`{}` is an input population, so it cannot receive synapses",
+                        projection.to
+                    ),
+                ));
+            }
+            if !is_population(&projection.to) {
+                return Err(not_a_population(&projection.to));
+            }
+        }
+        for monitor in monitors.iter() {
+            if !is_input(&monitor.population) && !is_population(&monitor.population) {
+                return Err(not_a_population(&monitor.population));
+            }
+        }
+        Ok(NetworkDef {
+            type_name,
+            voltage_type,
+            time_type,
+            params,
+            inputs,
+            populations,
+            projections,
+            monitors,
+        })
+    }
+}
+
+/// The local variable that holds something of the network while it is being
+/// built or stepped, out of reach of the user's code.
+fn local(prefix: &str, name: &Ident) -> Ident {
+    Ident::new(&format!("{}_{}", prefix, name), Span::mixed_site())
+}
+
+/// The closure that makes a neuron or synapse from its indices, which only
+/// binds the indices that it uses.
+fn maker(make: &Make, typ: &Type, indices: &[&str]) -> TokenStream {
+    let body = make.expand(typ);
+    let params: Vec<TokenStream> = indices
+        .iter()
+        .map(|index| {
+            let ident = Ident::new(index, Span::call_site());
+            if mentions(body.clone(), &ident) {
+                quote!(#ident)
+            } else {
+                quote!(_)
+            }
+        })
+        .collect();
+    quote!(|#(#params),*| #body)
+}
+
+pub fn expand_network(def: NetworkDef) -> TokenStream {
+    let NetworkDef {
+        type_name,
+        voltage_type,
+        time_type,
+        params,
+        inputs,
+        populations,
+        projections,
+        monitors,
+    } = def;
+
+    let all_populations: Vec<&Population> = inputs.iter().chain(populations.iter()).collect();
+    let pop_names: Vec<&Ident> = all_populations.iter().map(|p| &p.name).collect();
+    let pop_types: Vec<&Type> = all_populations.iter().map(|p| &p.typ).collect();
+    let pop_locals: Vec<Ident> = pop_names.iter().map(|n| local("population", n)).collect();
+    let pop_sizes: Vec<&Expr> = all_populations.iter().map(|p| &p.size).collect();
+    let pop_makers: Vec<TokenStream> = all_populations
+        .iter()
+        .map(|p| maker(&p.make, &p.typ, &["i"]))
+        .collect();
+    // Naming the traits next to the types puts any error where they are
+    // declared rather than in the step.
+    let trait_checks = inputs
+        .iter()
+        .map(|p| {
+            let typ = &p.typ;
+            quote_spanned! {typ.span()=>
+                let _: fn(&mut #typ, #time_type) =
+                    <#typ as ::brian_rs::spike_generators::InputSpikeGenerator<
+                        #voltage_type,
+                        #time_type,
+                    >>::advance;
+            }
+        })
+        .chain(populations.iter().map(|p| {
+            let typ = &p.typ;
+            quote_spanned! {typ.span()=>
+                let _: fn(&mut #typ, #voltage_type, #time_type) =
+                    <#typ as ::brian_rs::spike_generators::InnerSpikeGenerator<
+                        #voltage_type,
+                        #time_type,
+                    >>::handle_input;
+            }
+        }))
+        .chain(projections.iter().map(|p| {
+            let typ = &p.typ;
+            quote_spanned! {typ.span()=>
+                let _: fn(&mut #typ, #time_type) =
+                    <#typ as ::brian_rs::synapses::Synaptic<
+                        #voltage_type,
+                        #time_type,
+                    >>::advance_once;
+            }
+        }));
+
+    let proj_names: Vec<&Ident> = projections.iter().map(|p| &p.name).collect();
+    let proj_types: Vec<&Type> = projections.iter().map(|p| &p.typ).collect();
+    let proj_builds = projections.iter().map(|p| {
+        let (from, to) = (local("population", &p.from), local("population", &p.to));
+        let rule = p
+            .rule
+            .clone()
+            .unwrap_or_else(|| parse_quote!(::brian_rs::connect::Rule::AllToAll));
+        let seed = p.seed.clone().unwrap_or_else(|| parse_quote!(0));
+        let make = maker(&p.make, &p.typ, &["i", "j"]);
+        let [rule_local, make_local, group_local, group] = ["rule", "make", "connections", "group"]
+            .map(|name| Ident::new(name, Span::mixed_site()));
+        let set_delay = p.delay.as_ref().map(|delay| {
+            let (i, j) = (format_ident!("i"), format_ident!("j"));
+            let delay_tokens = delay.to_token_stream();
+            if mentions(delay_tokens.clone(), &i) || mentions(delay_tokens, &j) {
+                quote!(#group.set_delays(|#i, #j| #delay, dt);)
+            } else {
+                quote!(#group.set_delay(#delay, dt);)
+            }
+        });
+        let mutability = set_delay.as_ref().map(|_| quote!(mut));
+        quote! {{
+            let #rule_local: ::brian_rs::connect::Rule = #rule;
+            let #make_local = #make;
+            let #group_local = #rule_local
+                .pairs(#from.len(), #to.len(), #seed)
+                .into_iter()
+                .map(|(pre, post)| {
+                    ::brian_rs::synapses::Connection::new(pre, post, #make_local(pre, post))
+                })
+                .collect();
+            let #mutability #group =
+                ::brian_rs::synapses::SynapseGroup::new(#from.len(), #to.len(), #group_local);
+            #set_delay
+            #group
+        }}
+    });
+    let proj_ports = projections.iter().map(|p| match &p.port {
+        None => quote!(0),
+        Some(port) => {
+            let to = local("population", &p.to);
+            let (port_name, to_name) = (port.to_string(), p.to.to_string());
+            quote! {
+                ::brian_rs::network::port_index::<_, #voltage_type, #time_type>(&#to, #port_name)
+                    .unwrap_or_else(|| panic!("`{}` has no port `{}`", #to_name, #port_name))
+            }
+        }
+    });
+    let num_projections = projections.len();
+
+    // The step reads the voltages of the presynaptic populations before the
+    // neurons are advanced, and the spikes and voltages of the populations
+    // that synapses learn from or that are monitored after.
+    let voltages = |name: &Ident| local("voltages", name);
+    let spikes = |name: &Ident| local("spikes", name);
+    let inputs_of = |name: &Ident| local("inputs", name);
+    let mut sent: Vec<&Ident> = Vec::new();
+    let mut after_voltages: Vec<&Ident> = Vec::new();
+    let mut after_spikes: Vec<&Ident> = Vec::new();
+    for p in projections.iter() {
+        for name in [&p.from, &p.to] {
+            if !after_voltages.contains(&name) {
+                after_voltages.push(name);
+            }
+            if !after_spikes.contains(&name) {
+                after_spikes.push(name);
+            }
+        }
+        if !sent.contains(&&p.from) {
+            sent.push(&p.from);
+        }
+    }
+    for m in monitors.iter() {
+        let needed = match m.kind {
+            MonitorKind::Spikes => &mut after_spikes,
+            MonitorKind::Voltages => &mut after_voltages,
+        };
+        if !needed.contains(&&m.population) {
+            needed.push(&m.population);
+        }
+    }
+    let sent_locals: Vec<Ident> = sent.iter().map(|n| voltages(n)).collect();
+    let after_voltage_locals: Vec<Ident> = after_voltages.iter().map(|n| voltages(n)).collect();
+    let after_spike_locals: Vec<Ident> = after_spikes.iter().map(|n| spikes(n)).collect();
+
+    let input_names: Vec<&Ident> = inputs.iter().map(|p| &p.name).collect();
+    let inner_names: Vec<&Ident> = populations.iter().map(|p| &p.name).collect();
+    let inner_inputs: Vec<Ident> = inner_names.iter().map(|n| inputs_of(n)).collect();
+    let inner_mutability: Vec<Option<TokenStream>> = inner_names
+        .iter()
+        .map(|n| projections.iter().any(|p| &&p.to == n).then(|| quote!(mut)))
+        .collect();
+    let proj_froms: Vec<Ident> = projections.iter().map(|p| voltages(&p.from)).collect();
+    let proj_tos: Vec<Ident> = projections.iter().map(|p| inputs_of(&p.to)).collect();
+    let proj_indices: Vec<usize> = (0..projections.len()).collect();
+    let learning: Vec<TokenStream> = projections
+        .iter()
+        .map(|p| {
+            let name = &p.name;
+            let (from_spikes, from_voltages) = (spikes(&p.from), voltages(&p.from));
+            let (to_spikes, to_voltages) = (spikes(&p.to), voltages(&p.to));
+            quote! {
+                self.#name.learn(&#from_spikes, &#from_voltages, &#to_spikes, &#to_voltages, dt);
+            }
+        })
+        .collect();
+    let recordings: Vec<TokenStream> = monitors
+        .iter()
+        .map(|m| {
+            let field = m.field();
+            match m.kind {
+                MonitorKind::Spikes => {
+                    let spiked = spikes(&m.population);
+                    quote! {
+                        let time = self.time;
+                        self.#field.extend(
+                            #spiked
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, &spiked)| spiked)
+                                .map(|(idx, _)| (time, idx)),
+                        );
+                    }
+                }
+                MonitorKind::Voltages => {
+                    let recorded = voltages(&m.population);
+                    quote!(self.#field.push(#recorded.clone());)
+                }
+            }
+        })
+        .collect();
+    let monitor_fields: Vec<Ident> = monitors.iter().map(|m| m.field()).collect();
+    let monitor_types: Vec<TokenStream> = monitors
+        .iter()
+        .map(|m| match m.kind {
+            MonitorKind::Spikes => quote!(Vec<(#time_type, usize)>),
+            MonitorKind::Voltages => quote!(Vec<Vec<#voltage_type>>),
+        })
+        .collect();
+    let monitor_getters = monitors.iter().map(|m| {
+        let field = m.field();
+        match m.kind {
+            MonitorKind::Spikes => quote! {
+                /// The spikes recorded so far, as `(time, neuron)`.
+                pub fn #field(&self) -> &[(#time_type, usize)] {
+                    &self.#field
+                }
+            },
+            MonitorKind::Voltages => quote! {
+                /// The voltages recorded so far, one `Vec` per step.
+                pub fn #field(&self) -> &[Vec<#voltage_type>] {
+                    &self.#field
+                }
+            },
+        }
+    });
+
+    // The body of `step` or `par_step`, which updates the populations with
+    // `update` after the synapses `propagate` the voltages into their inputs,
+    // in the same order as `Network::step` and with the same helpers.
+    let step_body = |update: TokenStream, propagate: TokenStream| {
+        quote! {
+            let dt = self.dt;
+            #(
+                let #sent_locals: Vec<#voltage_type> =
+                    ::brian_rs::network::layer_voltages(&self.#sent);
+            )*
+            // The inputs of each population, by port and then by neuron.
+            #(
+                let #inner_mutability #inner_inputs: Vec<Vec<#voltage_type>> =
+                    ::brian_rs::network::zero_inputs::<_, _, #time_type>(&self.#inner_names);
+            )*
+            #(
+                #propagate(
+                    &mut self.#proj_names,
+                    &#proj_froms,
+                    &mut #proj_tos[self.ports[#proj_indices]],
+                );
+            )*
+
+            #(
+                ::brian_rs::network::advance_input_layer::<_, #voltage_type, _>(
+                    &mut self.#input_names,
+                    dt,
+                );
+            )*
+            #(#update(&mut self.#inner_names, #inner_inputs, dt);)*
+
+            // The synapses learn from the spikes of this step.
+            #(
+                let #after_voltage_locals: Vec<#voltage_type> =
+                    ::brian_rs::network::layer_voltages(&self.#after_voltages);
+            )*
+            #(
+                let #after_spike_locals: Vec<bool> =
+                    ::brian_rs::network::layer_spikes::<_, #voltage_type>(&self.#after_spikes);
+            )*
+            #(#learning)*
+            #(#recordings)*
+            self.time += dt;
+            #(self.#proj_names.advance_synapses(dt);)*
+        }
+    };
+    let step = step_body(
+        quote!(::brian_rs::network::update_layer),
+        quote!(::brian_rs::synapses::SynapseGroup::propagate),
+    );
+    let par_step = step_body(
+        quote!(::brian_rs::network::par_update_layer),
+        quote!(::brian_rs::synapses::SynapseGroup::par_propagate),
+    );
+    let inner_types: Vec<&Type> = populations.iter().map(|p| &p.typ).collect();
+
+    quote! {
+    pub struct #type_name {
+        #(pub #pop_names: Vec<#pop_types>,)*
+        #(
+            pub #proj_names:
+                ::brian_rs::synapses::SynapseGroup<#proj_types, #voltage_type, #time_type>,
+        )*
+        #(#monitor_fields: #monitor_types,)*
+        ports: [usize; #num_projections],
+        dt: #time_type,
+        time: #time_type,
+    }
+
+    impl #type_name {
+        /// Builds the network, to be stepped by `dt`.
+        ///
+        /// Panics if a connection rule cannot be met or if a population has
+        /// no port that synapses are routed to.
+        pub fn new(dt: #time_type #(, #params)*) -> Self {
+            #(#trait_checks)*
+            #(
+                let #pop_locals: Vec<#pop_types> = (0..#pop_sizes).map(#pop_makers).collect();
+            )*
+            #type_name {
+                #(#proj_names: #proj_builds,)*
+                ports: [#(#proj_ports),*],
+                #(#pop_names: #pop_locals,)*
+                #(#monitor_fields: Vec::new(),)*
+                dt,
+                time: ::brian_rs::network::start_time(),
+            }
+        }
+
+        /// The time simulated so far.
+        pub fn time(&self) -> #time_type {
+            self.time
+        }
+
+        pub fn dt(&self) -> #time_type {
+            self.dt
+        }
+
+        #(#monitor_getters)*
+
+        /// Advances the whole network by one time-step. As in a `Network`,
+        /// every input is computed from the state at the start of the step.
+        pub fn step(&mut self) {
+            #step
+        }
+
+        /// Runs the network for `duration`. The number of steps is
+        /// `duration / dt`, rounded to the nearest integer.
+        pub fn run(&mut self, duration: #time_type) {
+            for _ in 0..::brian_rs::network::num_steps(duration, self.dt) {
+                self.step();
+            }
+        }
+
+        /// As `step`, updating the populations in parallel. The bounds are
+        /// higher-ranked so that they are only checked where it is called,
+        /// and networks of types that are not thread-safe still compile.
+        pub fn par_step(&mut self)
+        where
+            #(for<'a> #inner_types: Send,)*
+            #(for<'a> #proj_types: Send,)*
+            for<'a> #voltage_type: Send,
+            for<'a> #time_type: Send + Sync,
+        {
+            #par_step
+        }
+
+        /// As `run`, updating the populations in parallel.
+        pub fn par_run(&mut self, duration: #time_type)
+        where
+            #(for<'a> #inner_types: Send,)*
+            #(for<'a> #proj_types: Send,)*
+            for<'a> #voltage_type: Send,
+            for<'a> #time_type: Send + Sync,
+        {
+            for _ in 0..::brian_rs::network::num_steps(duration, self.dt) {
+                self.par_step();
+            }
+        }
+    }
+    }
+}
@@ -0,0 +1,169 @@
+//! Clocks and a scheduler so that different parts of a simulation can run with
+//! different time-steps.
+//!
+//! For instance, a population of Hodgkin-Huxley neurons may need a 0.01 ms
+//! time-step while plasticity and monitors are fine with 1 ms. Each part of
+//! the simulation is added to a `Scheduler` with its own clock, and the
+//! scheduler interleaves them in time. Parts that are due at the same time run
+//! in the order of their `Slot` and then of their `order`, like Brian's `when`
+//! and `order` arguments. A `Network` can be split between two clocks this way
+//! by scheduling its `step_neurons` and `step_synapses` separately.
+
+extern crate dimensioned as dim;
+
+use dim::si;
+
+/// Counts the steps of a fixed time-step. The time is computed as
+/// `steps * dt` so that it does not drift as steps are added.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock<T> {
+    dt: T,
+    steps: u64,
+}
+
+impl<T> Clock<T>
+where
+    T: From<si::Second<f64>> + Into<si::Second<f64>> + Copy,
+{
+    pub fn new(dt: T) -> Self {
+        Clock { dt, steps: 0 }
+    }
+
+    pub fn dt(&self) -> T {
+        self.dt
+    }
+
+    /// The number of steps taken so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The time at the start of the next step.
+    pub fn time(&self) -> T {
+        (self.seconds() * si::S).into()
+    }
+
+    pub fn tick(&mut self) {
+        self.steps += 1;
+    }
+
+    fn seconds(&self) -> f64 {
+        let dt: si::Second<f64> = self.dt.into();
+        self.steps as f64 * *(dt / si::S)
+    }
+}
+
+/// When, within a time-step, something should run. Things in earlier slots
+/// run first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Slot {
+    /// Before anything else, such as for inputs.
+    Start,
+    /// Updating neuron groups.
+    Groups,
+    /// Updating synapses, such as for plasticity.
+    Synapses,
+    /// After everything else, such as for monitors.
+    End,
+}
+
+struct Scheduled<'a, T> {
+    clock: Clock<T>,
+    when: Slot,
+    order: i32,
+    action: Box<dyn FnMut(T) + 'a>,
+}
+
+/// Runs actions, each on its own clock, in time order.
+///
+/// An action is handed its time-step every time its clock ticks. All the
+/// actions due at the same time run in the order of `(when, order)`, and
+/// then in the order they were added.
+pub struct Scheduler<'a, T> {
+    scheduled: Vec<Scheduled<'a, T>>,
+    time: T,
+}
+
+impl<'a, T> Scheduler<'a, T>
+where
+    T: From<si::Second<f64>> + Into<si::Second<f64>> + Copy,
+{
+    pub fn new() -> Self {
+        Scheduler {
+            scheduled: Vec::new(),
+            time: (0.0 * si::S).into(),
+        }
+    }
+
+    /// Runs `action` every `dt`, in the given slot. The first run is at the
+    /// current time of the scheduler.
+    pub fn add<F: FnMut(T) + 'a>(&mut self, dt: T, when: Slot, order: i32, action: F) {
+        let mut clock = Clock::new(dt);
+        let now: si::Second<f64> = self.time.into();
+        let dt_seconds: si::Second<f64> = dt.into();
+        while clock.seconds() < *(now / si::S) - Self::tolerance(*(dt_seconds / si::S)) {
+            clock.tick();
+        }
+        self.scheduled.push(Scheduled {
+            clock,
+            when,
+            order,
+            action: Box::new(action),
+        });
+        // A stable sort keeps the order in which actions were added.
+        self.scheduled.sort_by_key(|s| (s.when, s.order));
+    }
+
+    /// The time simulated so far.
+    pub fn time(&self) -> T {
+        self.time
+    }
+
+    /// Runs every action due in the next `duration`.
+    pub fn run(&mut self, duration: T) {
+        let start: si::Second<f64> = self.time.into();
+        let duration: si::Second<f64> = duration.into();
+        let end = *((start + duration) / si::S);
+        let min_dt = self
+            .scheduled
+            .iter()
+            .map(|s| {
+                let dt: si::Second<f64> = s.clock.dt.into();
+                *(dt / si::S)
+            })
+            .fold(f64::INFINITY, f64::min);
+        let tolerance = Self::tolerance(min_dt);
+        loop {
+            let next = self
+                .scheduled
+                .iter()
+                .map(|s| s.clock.seconds())
+                .fold(f64::INFINITY, f64::min);
+            if next >= end - tolerance {
+                break;
+            }
+            for scheduled in self.scheduled.iter_mut() {
+                if (scheduled.clock.seconds() - next).abs() <= tolerance {
+                    (scheduled.action)(scheduled.clock.dt);
+                    scheduled.clock.tick();
+                }
+            }
+            self.time = (next * si::S).into();
+        }
+        self.time = (end * si::S).into();
+    }
+
+    /// How close two times must be to count as the same tick.
+    fn tolerance(dt: f64) -> f64 {
+        dt * 1e-9
+    }
+}
+
+impl<'a, T> Default for Scheduler<'a, T>
+where
+    T: From<si::Second<f64>> + Into<si::Second<f64>> + Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
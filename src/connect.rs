@@ -0,0 +1,130 @@
+//! Rules for connecting two populations of neurons, in the style of Brian's
+//! `Synapses.connect`.
+//!
+//! The rules only pick which pairs of neurons are connected. The synapses
+//! themselves come from a function of the pair, which would usually just call
+//! the `new` of a type made by `define_synapse!`. The random rules take a seed
+//! so that the same seed always gives the same topology. They draw from
+//! ChaCha8, whose output is fixed for a given seed, unlike that of `StdRng`,
+//! which may change between versions of rand.
+
+extern crate rand;
+extern crate rand_chacha;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::synapses::{Connection, SynapseGroup};
+
+/// Which pairs of neurons `(i, j)` to connect, for `i` in the presynaptic
+/// population and `j` in the postsynaptic one.
+pub enum Rule {
+    /// Connects every pair.
+    AllToAll,
+    /// Connects `i` to `j` only if `i == j`. The populations must be the same
+    /// size.
+    OneToOne,
+    /// Connects every pair independently with the given probability.
+    Probability(f64),
+    /// Connects each postsynaptic neuron to this many distinct presynaptic
+    /// neurons, picked uniformly.
+    FixedInDegree(usize),
+    /// Connects each presynaptic neuron to this many distinct postsynaptic
+    /// neurons, picked uniformly.
+    FixedOutDegree(usize),
+    /// Connects the pairs for which the function is true.
+    Predicate(Box<dyn Fn(usize, usize) -> bool>),
+}
+
+impl Rule {
+    /// Lists the pairs `(pre, post)` to connect between populations of
+    /// `num_pre` and `num_post` neurons, sorted by `pre` then `post`.
+    ///
+    /// Panics if the rule cannot be met by populations of those sizes.
+    pub fn pairs(&self, num_pre: usize, num_post: usize, seed: u64) -> Vec<(usize, usize)> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut pairs: Vec<(usize, usize)> = match self {
+            Rule::AllToAll => (0..num_pre)
+                .flat_map(|i| (0..num_post).map(move |j| (i, j)))
+                .collect(),
+            Rule::OneToOne => {
+                assert_eq!(
+                    num_pre, num_post,
+                    "One-to-one connections need populations of the same size"
+                );
+                (0..num_pre).map(|i| (i, i)).collect()
+            }
+            Rule::Probability(p) => {
+                assert!(
+                    (0.0..=1.0).contains(p),
+                    "Invalid connection probability {}",
+                    p
+                );
+                (0..num_pre)
+                    .flat_map(|i| (0..num_post).map(move |j| (i, j)))
+                    .filter(|_| rng.gen_bool(*p))
+                    .collect()
+            }
+            Rule::FixedInDegree(k) => {
+                assert!(
+                    *k <= num_pre,
+                    "Cannot pick {} inputs from {} neurons",
+                    k,
+                    num_pre
+                );
+                (0..num_post)
+                    .flat_map(|j| {
+                        rand::seq::index::sample(&mut rng, num_pre, *k)
+                            .into_iter()
+                            .map(move |i| (i, j))
+                            .collect::<Vec<(usize, usize)>>()
+                    })
+                    .collect()
+            }
+            Rule::FixedOutDegree(k) => {
+                assert!(
+                    *k <= num_post,
+                    "Cannot pick {} outputs from {} neurons",
+                    k,
+                    num_post
+                );
+                (0..num_pre)
+                    .flat_map(|i| {
+                        rand::seq::index::sample(&mut rng, num_post, *k)
+                            .into_iter()
+                            .map(move |j| (i, j))
+                            .collect::<Vec<(usize, usize)>>()
+                    })
+                    .collect()
+            }
+            Rule::Predicate(f) => (0..num_pre)
+                .flat_map(|i| (0..num_post).map(move |j| (i, j)))
+                .filter(|&(i, j)| f(i, j))
+                .collect(),
+        };
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+/// Connects `num_pre` neurons to `num_post` neurons according to the rule,
+/// making the synapse from `i` to `j` with `make_synapse(i, j)`.
+///
+/// Panics if the rule cannot be met by populations of those sizes.
+pub fn connect<S, V, T, F>(
+    num_pre: usize,
+    num_post: usize,
+    rule: &Rule,
+    seed: u64,
+    mut make_synapse: F,
+) -> SynapseGroup<S, V, T>
+where
+    F: FnMut(usize, usize) -> S,
+{
+    let connections = rule
+        .pairs(num_pre, num_post, seed)
+        .into_iter()
+        .map(|(i, j)| Connection::new(i, j, make_synapse(i, j)))
+        .collect();
+    SynapseGroup::new(num_pre, num_post, connections)
+}
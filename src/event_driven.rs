@@ -0,0 +1,245 @@
+//! An event-driven alternative to stepping a whole network with a fixed `dt`.
+//!
+//! For sparsely firing networks most neurons do nothing on most steps. Here,
+//! neurons are only advanced when something happens to them: an input arrives
+//! or a spike they were predicted to fire is due. This needs neurons whose
+//! state between events has a closed form, which `ClosedFormNeuron` captures.
+//! Spikes and inputs wait in a priority queue ordered by their time.
+
+extern crate dimensioned as dim;
+
+use dim::si;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::spike_generators::SpikeGenerator;
+
+/// A neuron whose state can be advanced by any amount of time in one go,
+/// since its state between inputs has a closed form (as for leaky
+/// integrate-and-fire neurons).
+pub trait ClosedFormNeuron<V, T>: SpikeGenerator<V> {
+    /// Advances the state by `dt` with no input.
+    fn evolve(&mut self, dt: T);
+    /// Handles an input that arrives instantaneously (such as a jump in the
+    /// membrane voltage).
+    fn receive(&mut self, input: V);
+    /// Resets the neuron after a spike.
+    fn reset(&mut self);
+    /// How long until the neuron would spike if it got no more inputs. This
+    /// is `None` for neurons that never spike on their own.
+    fn next_spike_in(&self) -> Option<T> {
+        None
+    }
+}
+
+enum EventKind<V> {
+    Input { target: usize, input: V },
+    PredictedSpike { neuron: usize, version: u64 },
+}
+
+struct Event<V, T> {
+    time: T,
+    key: f64,
+    seq: u64,
+    kind: EventKind<V>,
+}
+
+// The heap is a max-heap, so events are ordered backwards: earliest first,
+// with ties broken by the order in which they were scheduled.
+impl<V, T> Ord for Event<V, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .total_cmp(&self.key)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<V, T> PartialOrd for Event<V, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<V, T> PartialEq for Event<V, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<V, T> Eq for Event<V, T> {}
+
+/// An outgoing synapse: a fixed weight and a transmission delay.
+struct Edge<T> {
+    target: usize,
+    weight: f64,
+    delay: T,
+}
+
+/// A population of closed-form neurons connected by weighted, delayed
+/// synapses and simulated from event to event.
+///
+/// When a neuron spikes, its voltage (read just before it resets) times the
+/// weight of each outgoing synapse is delivered to the target after the
+/// synapse's delay.
+pub struct EventDrivenNetwork<N, V, T> {
+    neurons: Vec<N>,
+    last_update: Vec<T>,
+    versions: Vec<u64>,
+    edges: Vec<Vec<Edge<T>>>,
+    queue: BinaryHeap<Event<V, T>>,
+    seq: u64,
+    time: T,
+    spikes: Vec<(T, usize)>,
+}
+
+impl<N, V, T> EventDrivenNetwork<N, V, T>
+where
+    N: ClosedFormNeuron<V, T>,
+    V: Copy + std::ops::Mul<f64, Output = V>,
+    T: From<si::Second<f64>>
+        + Into<si::Second<f64>>
+        + Copy
+        + PartialOrd
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>,
+{
+    pub fn new(neurons: Vec<N>) -> Self {
+        let mut network = EventDrivenNetwork {
+            last_update: neurons.iter().map(|_| (0.0 * si::S).into()).collect(),
+            versions: vec![0; neurons.len()],
+            edges: neurons.iter().map(|_| Vec::new()).collect(),
+            neurons,
+            queue: BinaryHeap::new(),
+            seq: 0,
+            time: (0.0 * si::S).into(),
+            spikes: Vec::new(),
+        };
+        for neuron in 0..network.neurons.len() {
+            network.predict_spike(neuron);
+        }
+        network
+    }
+
+    /// Adds a synapse from `pre` to `post`.
+    pub fn connect(&mut self, pre: usize, post: usize, weight: f64, delay: T) {
+        assert!(
+            pre < self.neurons.len() && post < self.neurons.len(),
+            "Connection {} -> {} is out of bounds for {} neurons",
+            pre,
+            post,
+            self.neurons.len()
+        );
+        self.edges[pre].push(Edge {
+            target: post,
+            weight,
+            delay,
+        });
+    }
+
+    /// Schedules an external input to a neuron at the given time.
+    ///
+    /// Panics if the time has already been simulated.
+    pub fn schedule_input(&mut self, time: T, target: usize, input: V) {
+        assert!(time >= self.time, "Cannot schedule an input in the past");
+        self.push(time, EventKind::Input { target, input });
+    }
+
+    /// The time simulated so far.
+    pub fn time(&self) -> T {
+        self.time
+    }
+
+    /// Every spike so far, as `(time, neuron)` in the order they happened.
+    pub fn spikes(&self) -> &[(T, usize)] {
+        &self.spikes
+    }
+
+    /// Gets a neuron, advanced to the current time.
+    pub fn neuron(&mut self, idx: usize) -> &N {
+        self.evolve_to(idx, self.time);
+        &self.neurons[idx]
+    }
+
+    /// Handles every event up to and including `end`.
+    ///
+    /// Panics if `end` is before the time already simulated.
+    pub fn run_until(&mut self, end: T) {
+        assert!(end >= self.time, "Cannot run until a time in the past");
+        while self.queue.peek().is_some_and(|event| event.time <= end) {
+            let event = self.queue.pop().unwrap();
+            self.time = event.time;
+            match event.kind {
+                EventKind::Input { target, input } => {
+                    self.evolve_to(target, event.time);
+                    self.neurons[target].receive(input);
+                    self.versions[target] += 1;
+                    if self.neurons[target].did_spike() {
+                        self.fire(target);
+                    } else {
+                        self.predict_spike(target);
+                    }
+                }
+                EventKind::PredictedSpike { neuron, version } => {
+                    // Any input since the prediction makes it stale.
+                    if version == self.versions[neuron] {
+                        self.evolve_to(neuron, event.time);
+                        self.fire(neuron);
+                    }
+                }
+            }
+        }
+        self.time = end;
+    }
+
+    fn push(&mut self, time: T, kind: EventKind<V>) {
+        let key: si::Second<f64> = time.into();
+        self.queue.push(Event {
+            time,
+            key: *(key / si::S),
+            seq: self.seq,
+            kind,
+        });
+        self.seq += 1;
+    }
+
+    fn evolve_to(&mut self, neuron: usize, time: T) {
+        if time > self.last_update[neuron] {
+            self.neurons[neuron].evolve(time - self.last_update[neuron]);
+            self.last_update[neuron] = time;
+        }
+    }
+
+    fn predict_spike(&mut self, neuron: usize) {
+        if let Some(dt) = self.neurons[neuron].next_spike_in() {
+            let version = self.versions[neuron];
+            self.push(
+                self.last_update[neuron] + dt,
+                EventKind::PredictedSpike { neuron, version },
+            );
+        }
+    }
+
+    fn fire(&mut self, neuron: usize) {
+        self.spikes.push((self.time, neuron));
+        let voltage = self.neurons[neuron].get_voltage();
+        self.neurons[neuron].reset();
+        self.versions[neuron] += 1;
+        let outgoing: Vec<(T, EventKind<V>)> = self.edges[neuron]
+            .iter()
+            .map(|edge| {
+                (
+                    self.time + edge.delay,
+                    EventKind::Input {
+                        target: edge.target,
+                        input: voltage * edge.weight,
+                    },
+                )
+            })
+            .collect();
+        for (time, kind) in outgoing {
+            self.push(time, kind);
+        }
+        self.predict_spike(neuron);
+    }
+}
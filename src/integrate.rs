@@ -0,0 +1,206 @@
+//! Numerical routines for the integrators that `define_neuron!` and
+//! `define_synapse!` generate. They work on the magnitudes of the state
+//! variables (see `reflect::Magnitude`), so that a system can mix units.
+
+/// The most Newton iterations `newton` takes.
+const MAX_ITERATIONS: usize = 50;
+
+/// The change of a variable, relative to its size, under which Newton's
+/// method is taken to have converged.
+const TOLERANCE: f64 = 1e-10;
+
+/// Solves `residual(y) = 0` with Newton's method, starting from `y`, and
+/// leaves the solution in `y`. The Jacobian is estimated with finite
+/// differences, so that coupled systems are solved as a whole.
+///
+/// Returns whether the iteration converged, which is when every variable
+/// changes by less than `1e-10` times the larger of its size and that of
+/// its starting value, or the residual is zero.
+pub fn newton<const N: usize>(
+    y: &mut [f64; N],
+    mut residual: impl FnMut(&[f64; N]) -> [f64; N],
+) -> bool {
+    let start = *y;
+    for _ in 0..MAX_ITERATIONS {
+        let value = residual(y);
+        if value.iter().all(|r| *r == 0.0) {
+            return true;
+        }
+        if value.iter().any(|r| !r.is_finite()) {
+            return false;
+        }
+        let mut jacobian = [[0.0; N]; N];
+        for j in 0..N {
+            let scale = y[j].abs().max(start[j].abs());
+            let mut shifted = *y;
+            shifted[j] += f64::EPSILON.sqrt() * if scale > 0.0 { scale } else { 1.0 };
+            // The step that was actually taken, after rounding.
+            let h = shifted[j] - y[j];
+            let moved = residual(&shifted);
+            for i in 0..N {
+                jacobian[i][j] = (moved[i] - value[i]) / h;
+            }
+        }
+        let change = match solve(jacobian, value) {
+            Some(change) => change,
+            None => return false,
+        };
+        let mut converged = true;
+        for i in 0..N {
+            y[i] -= change[i];
+            converged &= change[i].abs() <= TOLERANCE * y[i].abs().max(start[i].abs());
+        }
+        if converged {
+            return true;
+        }
+    }
+    false
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting, or
+/// returns `None` if `a` is singular.
+#[allow(clippy::needless_range_loop)]
+fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col] == 0.0 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..N {
+            let factor = a[row][col] / a[col][col];
+            for k in col..N {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// The terms of the Taylor series of the exponential that `Propagator` sums.
+/// The series is summed for matrices with a norm of at most one half, for
+/// which the last term is under `1e-20`.
+const SERIES_TERMS: usize = 18;
+
+/// The exact step of a linear system `x' = A x + b`, which is
+/// `x + dt phi(A dt) (A x + b)` with `phi(z) = (e^z - 1) / z`, or
+/// `e^(A dt) x + dt phi(A dt) b`.
+///
+/// The matrices `e^(A dt)` and `phi(A dt)` are kept from one step to the
+/// next, and only computed again when `A dt` changes, so a model whose
+/// coefficients and time-step stay the same pays for them once.
+pub struct Propagator<const N: usize> {
+    matrix: Option<[[f64; N]; N]>,
+    exp: [[f64; N]; N],
+    phi: [[f64; N]; N],
+}
+
+impl<const N: usize> Propagator<N> {
+    pub fn new() -> Self {
+        Propagator {
+            matrix: None,
+            exp: [[0.0; N]; N],
+            phi: [[0.0; N]; N],
+        }
+    }
+
+    /// The state after a step, given `A dt`, `b dt` and the state before
+    /// it.
+    pub fn step(
+        &mut self,
+        matrix: &[[f64; N]; N],
+        constant: &[f64; N],
+        state: &[f64; N],
+    ) -> [f64; N] {
+        if self.matrix.as_ref() != Some(matrix) {
+            let (exp, phi) = exp_and_phi(matrix);
+            self.matrix = Some(*matrix);
+            self.exp = exp;
+            self.phi = phi;
+        }
+        let mut next = [0.0; N];
+        for (i, next) in next.iter_mut().enumerate() {
+            for j in 0..N {
+                *next += self.exp[i][j] * state[j] + self.phi[i][j] * constant[j];
+            }
+        }
+        next
+    }
+}
+
+impl<const N: usize> Default for Propagator<N> {
+    fn default() -> Self {
+        Propagator::new()
+    }
+}
+
+/// `e^a` and `phi(a)`, by scaling and squaring: the series are summed for
+/// `a / 2^s`, with `s` chosen to bring its norm under one half, and then
+/// doubled `s` times with `e^2z = (e^z)^2` and `phi(2z) = phi(z) (e^z + 1) / 2`.
+fn exp_and_phi<const N: usize>(a: &[[f64; N]; N]) -> ([[f64; N]; N], [[f64; N]; N]) {
+    let norm = a
+        .iter()
+        .map(|row| row.iter().map(|x| x.abs()).sum::<f64>())
+        .fold(0.0, f64::max);
+    let squarings = if norm > 0.5 {
+        (norm / 0.5).log2().ceil() as i32
+    } else {
+        0
+    };
+    let scale = 0.5f64.powi(squarings);
+    let mut scaled = *a;
+    for x in scaled.iter_mut().flatten() {
+        *x *= scale;
+    }
+
+    let mut identity = [[0.0; N]; N];
+    for (i, row) in identity.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    // The terms are a^k / k! for the exponential and a^k / (k + 1)! for phi.
+    let (mut exp, mut phi, mut power) = (identity, identity, identity);
+    for k in 1..SERIES_TERMS {
+        power = product(&power, &scaled);
+        for x in power.iter_mut().flatten() {
+            *x /= k as f64;
+        }
+        for i in 0..N {
+            for j in 0..N {
+                exp[i][j] += power[i][j];
+                phi[i][j] += power[i][j] / (k + 1) as f64;
+            }
+        }
+    }
+
+    for _ in 0..squarings {
+        let mut half_sum = exp;
+        for (i, row) in half_sum.iter_mut().enumerate() {
+            row[i] += 1.0;
+            for x in row.iter_mut() {
+                *x *= 0.5;
+            }
+        }
+        phi = product(&phi, &half_sum);
+        exp = product(&exp, &exp);
+    }
+    (exp, phi)
+}
+
+fn product<const N: usize>(a: &[[f64; N]; N], b: &[[f64; N]; N]) -> [[f64; N]; N] {
+    let mut product = [[0.0; N]; N];
+    for i in 0..N {
+        for k in 0..N {
+            for j in 0..N {
+                product[i][j] += a[i][k] * b[k][j];
+            }
+        }
+    }
+    product
+}
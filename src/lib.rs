@@ -1,22 +1,42 @@
+// The code that the macros generate names this crate `brian_rs`, which
+// this makes work inside the crate too.
+extern crate self as brian_rs;
+
+pub mod clock;
+pub mod connect;
+pub mod event_driven;
+pub mod integrate;
+pub mod network;
+pub mod noise;
+pub mod parallel;
+pub mod reflect;
 pub mod spike_generators;
 pub mod synapses;
+pub mod units;
 
-
-// #[cfg(test)]
+#[cfg(test)]
 mod tests {
 
     extern crate brian_rs_macros;
     extern crate dimensioned as dim;
 
     use dim::si;
-    use std::vec;
 
+    use super::clock::{Scheduler, Slot};
+    use super::connect::{self, Rule};
+    use super::event_driven::{ClosedFormNeuron, EventDrivenNetwork};
+    use super::network::{
+        Connection, InputSnn, MiddleSnn, Network, NetworkBuilder, NoInput, INPUT_LAYER,
+    };
+    use super::noise::Noise;
+    use super::reflect::{Magnitude, Reflect};
     use super::spike_generators::{
         continuous::WithSpikeDecay,
         discrete::{SpikeAtRate, SpikeAtTimes},
         InnerSpikeGenerator, InputSpikeGenerator, SpikeGenerator,
     };
-    use super::synapses::Synaptic;
+    use super::synapses::{SynapseGroup, Synaptic};
+    use super::units::{Float, Quantity};
 
     #[test]
     fn spike_generator_at_times() {
@@ -30,6 +50,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::type_complexity)]
     fn exp_decay_on_generator() {
         let times = vec![1. * si::S, 2. * si::S];
         let discrete_spiker = SpikeAtTimes::new(times.clone(), 0.1 * si::S, 0.5 * si::V);
@@ -80,7 +101,7 @@ mod tests {
         let mut spike_count = 0;
         for i in 0..30 {
             if rate_neuron.did_spike() {
-                assert!(i >= 10 && i <= 20);
+                assert!((10..=20).contains(&i));
                 spike_count += 1;
             }
             rate_neuron.advance(0.1 * si::S);
@@ -129,4 +150,1543 @@ mod tests {
 	    self.w += self.a_pre
 	}
     }
+
+    type ConstantAfterSpike = WithSpikeDecay<
+        SpikeAtTimes<si::Second<f64>, si::Volt<f64>>,
+        si::Second<f64>,
+        si::Volt<f64>,
+    >;
+
+    fn constant_after(time: f64, voltage: f64) -> ConstantAfterSpike {
+        let spiker = SpikeAtTimes::new(vec![time * si::S], 0.01 * si::S, voltage * si::V);
+        WithSpikeDecay::exp_decay(spiker, 1.0, 0.0)
+    }
+
+    #[test]
+    fn network_sums_weighted_inputs() {
+        let inputs = vec![constant_after(0.5, 20.0), constant_after(0.5, 20.0)];
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let mut network = NetworkBuilder::new(inputs)
+            .layer(2, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+        let mut spike_counts = [0, 0];
+        for _ in 0..300 {
+            network.step(0.1 * si::S);
+            for (count, spiked) in spike_counts.iter_mut().zip(network.spikes(1)) {
+                if spiked {
+                    *count += 1;
+                }
+            }
+        }
+        assert!(spike_counts[0] > 0);
+        assert_eq!(spike_counts[1], 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Use `input_layer()` for layer 0")]
+    fn layer_rejects_the_input_layer() {
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let network = NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+            .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+        network.layer(INPUT_LAYER);
+    }
+
+    /// A neuron whose voltage is the input it got in the last step.
+    struct InputProbe {
+        input: si::Volt<f64>,
+    }
+
+    impl SpikeGenerator<si::Volt<f64>> for InputProbe {
+        fn did_spike(&self) -> bool {
+            false
+        }
+
+        fn get_voltage(&self) -> si::Volt<f64> {
+            self.input
+        }
+    }
+
+    impl InnerSpikeGenerator<si::Volt<f64>, si::Second<f64>> for InputProbe {
+        fn handle_input(&mut self, input: si::Volt<f64>, _dt: si::Second<f64>) {
+            self.input = input;
+        }
+    }
+
+    #[test]
+    fn network_sums_converging_inputs() {
+        let inputs = vec![
+            constant_after(0.5, 20.0),
+            constant_after(0.5, 10.0),
+            constant_after(0.5, 4.0),
+        ];
+        let synapse = |w: f64| StdpNeuron::new(10.0, 10.0, w * si::S / si::S, 0.0);
+        let connections = vec![
+            Connection::new(0, 0, synapse(1.0)),
+            Connection::new(1, 0, synapse(2.0)),
+            Connection::new(2, 0, synapse(0.5)),
+            Connection::new(2, 1, synapse(3.0)),
+        ];
+        let mut network = NetworkBuilder::new(inputs)
+            .layer(2, |_| InputProbe { input: 0.0 * si::V })
+            .connect(INPUT_LAYER, 1, connections)
+            .build();
+        network.run(1.0 * si::S, 0.1 * si::S);
+        assert_eq!(network.voltages(1), vec![42.0 * si::V, 12.0 * si::V]);
+    }
+
+    #[test]
+    fn network_run_trains_synapses() {
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.1);
+        let mut network = NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+            .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+        network.run(30.0 * si::S, 0.1 * si::S);
+        assert!(((network.time() - 30.0 * si::S) / si::S).abs() < 1e-6);
+        let (_, _, synapse) = network.connections(INPUT_LAYER, 1).next().unwrap();
+        assert!(synapse.current_weight() > 1.0);
+    }
+
+    #[test]
+    fn input_snn_as_input_layer() {
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let inner = NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+            .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+        let snn = InputSnn::new(inner, 1);
+
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let mut outer = NetworkBuilder::new(vec![snn])
+            .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+        let mut input_spikes = 0;
+        for _ in 0..300 {
+            outer.step(0.1 * si::S);
+            let snn = &outer.input_layer()[0];
+            assert_eq!(snn.did_spike(), snn.network().spikes(1)[0]);
+            assert_eq!(snn.get_voltage(), snn.network().voltages(1)[0]);
+            if snn.did_spike() {
+                input_spikes += 1;
+            }
+        }
+        assert!(input_spikes > 0);
+        assert_eq!(outer.input_layer()[0].network().time(), outer.time());
+    }
+
+    #[test]
+    fn middle_snn_matches_flat_network() {
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let mut flat = NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+            .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+
+        let make_middle = |_| {
+            let inner = NetworkBuilder::<NoInput, _, StdpNeuron, _, _>::new(Vec::new())
+                .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+                .build();
+            MiddleSnn::new(inner, 1, 1)
+        };
+        let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+        let mut nested = NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+            .layer(1, make_middle)
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build();
+
+        let mut spikes = 0;
+        for _ in 0..300 {
+            flat.step(0.1 * si::S);
+            nested.step(0.1 * si::S);
+            assert_eq!(flat.spikes(1), nested.spikes(1));
+            assert_eq!(flat.voltages(1), nested.voltages(1));
+            if flat.spikes(1)[0] {
+                spikes += 1;
+            }
+        }
+        assert!(spikes > 0);
+    }
+
+    #[test]
+    fn synapse_group_matches_eager_updates() {
+        let pairs = [(0, 1), (2, 0), (0, 0), (1, 1), (2, 1)];
+        let make_synapse = || StdpNeuron::new(10.0, 5.0, 1.0 * si::S / si::S, 0.1);
+        let mut group = SynapseGroup::new(
+            3,
+            2,
+            pairs
+                .iter()
+                .map(|&(pre, post)| Connection::new(pre, post, make_synapse()))
+                .collect(),
+        );
+        let mut eager: Vec<StdpNeuron> = pairs.iter().map(|_| make_synapse()).collect();
+
+        for step in 0..50 {
+            let pre_spikes = [step % 7 == 0, step == 20, step % 11 == 3];
+            let post_spikes = [step % 5 == 1, step % 13 == 0];
+            let pre_voltages = [0.5 * si::V; 3];
+            let post_voltages = [0.5 * si::V; 2];
+
+            let mut inputs = [0.0 * si::V; 2];
+            group.propagate(&[1.0 * si::V, 0.0 * si::V, 2.0 * si::V], &mut inputs);
+            let mut expected = [0.0 * si::V; 2];
+            for (&(pre, post), synapse) in pairs.iter().zip(eager.iter()) {
+                expected[post] += [1.0, 0.0, 2.0][pre] * si::V * synapse.current_weight();
+            }
+            assert_eq!(inputs, expected);
+
+            group.on_pre_spikes(&pre_spikes, &pre_voltages);
+            group.on_post_spikes(&post_spikes, &post_voltages);
+            group.advance(0.1 * si::S);
+            for (&(pre, post), synapse) in pairs.iter().zip(eager.iter_mut()) {
+                if pre_spikes[pre] {
+                    synapse.on_pre(pre_voltages[pre]);
+                }
+                if post_spikes[post] {
+                    synapse.on_post(post_voltages[post]);
+                }
+                synapse.advance_once(0.1 * si::S);
+            }
+        }
+
+        group.flush();
+        let mut grouped: Vec<(usize, usize, f64)> = group
+            .iter()
+            .map(|(pre, post, synapse)| (pre, post, synapse.current_weight()))
+            .collect();
+        let mut expected: Vec<(usize, usize, f64)> = pairs
+            .iter()
+            .zip(eager.iter())
+            .map(|(&(pre, post), synapse)| (pre, post, synapse.current_weight()))
+            .collect();
+        grouped.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(grouped, expected);
+    }
+
+    fn new_stdp(_: usize, _: usize) -> StdpNeuron {
+        StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0)
+    }
+
+    #[test]
+    fn connection_rules() {
+        let all: SynapseGroup<_, si::Volt<f64>, si::Second<f64>> =
+            connect::connect(3, 4, &Rule::AllToAll, 0, new_stdp);
+        assert_eq!(all.len(), 12);
+
+        let one = Rule::OneToOne.pairs(4, 4, 0);
+        assert_eq!(one, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+
+        let in_degree = Rule::FixedInDegree(3).pairs(10, 20, 7);
+        assert_eq!(in_degree.len(), 60);
+        for post in 0..20 {
+            assert_eq!(in_degree.iter().filter(|&&(_, j)| j == post).count(), 3);
+        }
+
+        let out_degree = Rule::FixedOutDegree(2).pairs(10, 20, 7);
+        assert_eq!(out_degree.len(), 20);
+        for pre in 0..10 {
+            assert_eq!(out_degree.iter().filter(|&&(i, _)| i == pre).count(), 2);
+        }
+
+        let predicate = Rule::Predicate(Box::new(|i, j| i < j)).pairs(3, 3, 0);
+        assert_eq!(predicate, vec![(0, 1), (0, 2), (1, 2)]);
+
+        let none = Rule::Probability(0.0).pairs(10, 10, 1);
+        assert!(none.is_empty());
+        let some = Rule::Probability(0.3).pairs(100, 100, 1);
+        assert!(some.len() > 2000 && some.len() < 4000);
+    }
+
+    #[test]
+    fn connection_rules_are_seeded() {
+        let rule = Rule::Probability(0.5);
+        assert_eq!(rule.pairs(30, 30, 42), rule.pairs(30, 30, 42));
+        assert_ne!(rule.pairs(30, 30, 42), rule.pairs(30, 30, 43));
+
+        let rule = Rule::FixedInDegree(5);
+        assert_eq!(rule.pairs(30, 30, 42), rule.pairs(30, 30, 42));
+
+        // The generator is pinned, so a seed gives this topology everywhere.
+        assert_eq!(
+            Rule::Probability(0.5).pairs(3, 3, 42),
+            vec![(0, 2), (1, 1), (1, 2), (2, 0)]
+        );
+    }
+
+    #[test]
+    fn network_connect_with_rule() {
+        let inputs = (0..5).map(|_| constant_after(0.5, 20.0)).collect();
+        let mut network = NetworkBuilder::new(inputs)
+            .layer(5, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+            .connect_with(INPUT_LAYER, 1, &Rule::OneToOne, 0, new_stdp)
+            .build();
+        let pairs: Vec<(usize, usize)> = network
+            .connections(INPUT_LAYER, 1)
+            .map(|(pre, post, _)| (pre, post))
+            .collect();
+        assert_eq!(pairs, Rule::OneToOne.pairs(5, 5, 0));
+    }
+
+    #[test]
+    fn parallel_steps_match_sequential_steps() {
+        let make_network = || {
+            let inputs = (0..8)
+                .map(|i| constant_after(0.2 + 0.1 * i as f64, 10.0 + i as f64))
+                .collect();
+            NetworkBuilder::new(inputs)
+                .layer(16, |i| Izikhevich::new(0.02, 0.2, -65.0 + i as f64, 8.0))
+                .layer(4, |_| Izikhevich::new(0.02, 0.25, -65.0, 2.0))
+                .connect_with(INPUT_LAYER, 1, &Rule::Probability(0.5), 1, new_stdp)
+                .connect_with(1, 2, &Rule::AllToAll, 0, new_stdp)
+                .build()
+        };
+        let mut sequential = make_network();
+        let mut parallel = make_network();
+        let mut spikes = 0;
+        for _ in 0..300 {
+            sequential.step(0.1 * si::S);
+            parallel.par_step(0.1 * si::S);
+            for layer in 1..3 {
+                assert_eq!(sequential.voltages(layer), parallel.voltages(layer));
+                assert_eq!(sequential.spikes(layer), parallel.spikes(layer));
+                spikes += sequential.spikes(layer).iter().filter(|&&s| s).count();
+            }
+        }
+        assert!(spikes > 0);
+        for (from, to) in [(INPUT_LAYER, 1), (1, 2)].iter().cloned() {
+            let weights = |network: &mut Network<_, _, StdpNeuron, _, _>| -> Vec<f64> {
+                network
+                    .connections(from, to)
+                    .map(|(_, _, s)| s.current_weight())
+                    .collect()
+            };
+            assert_eq!(weights(&mut sequential), weights(&mut parallel));
+        }
+    }
+
+    #[test]
+    fn synapse_group_delays() {
+        let make_synapse = || StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.1);
+        let mut group = SynapseGroup::new(
+            2,
+            1,
+            vec![
+                Connection::new(0, 0, make_synapse()),
+                Connection::new(1, 0, make_synapse()),
+            ],
+        );
+        let dt = 0.1 * si::S;
+        group.set_delays(|pre, _| (pre as f64) * 0.3 * si::S, dt);
+        assert_eq!(group.delays_in_steps(), vec![0, 3]);
+
+        for step in 0..6 {
+            let mut inputs = [0.0 * si::V];
+            let pre_voltages = if step == 0 {
+                [1.0 * si::V, 2.0 * si::V]
+            } else {
+                [0.0 * si::V; 2]
+            };
+            group.propagate(&pre_voltages, &mut inputs);
+            match step {
+                0 => assert_eq!(inputs, [1.0 * si::V]),
+                3 => assert_eq!(inputs, [2.0 * si::V]),
+                _ => assert_eq!(inputs, [0.0 * si::V]),
+            }
+
+            group.on_pre_spikes(&[step == 0, step == 0], &pre_voltages);
+            group.advance(dt);
+            let a_pres: Vec<bool> = group
+                .iter()
+                .map(|(_, _, synapse)| *synapse.a_pre != 0.0)
+                .collect();
+            assert_eq!(a_pres, vec![true, step >= 3]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The delays were set up for another time-step")]
+    fn synapse_group_delays_need_their_time_step() {
+        let mut group = SynapseGroup::<_, si::Volt<f64>, _>::new(
+            1,
+            1,
+            vec![Connection::new(0, 0, unit_synapse())],
+        );
+        group.set_delay(0.3 * si::S, 0.1 * si::S);
+        group.advance(0.1 * si::S);
+        group.advance(0.05 * si::S);
+    }
+
+    /// A leaky integrate-and-fire neuron relaxing towards `drive`, solved
+    /// exactly.
+    struct ExactLif {
+        v: si::Volt<f64>,
+        drive: si::Volt<f64>,
+        tau: si::Second<f64>,
+        threshold: si::Volt<f64>,
+    }
+
+    impl SpikeGenerator<si::Volt<f64>> for ExactLif {
+        fn did_spike(&self) -> bool {
+            self.v >= self.threshold
+        }
+
+        fn get_voltage(&self) -> si::Volt<f64> {
+            self.v
+        }
+    }
+
+    impl ClosedFormNeuron<si::Volt<f64>, si::Second<f64>> for ExactLif {
+        fn evolve(&mut self, dt: si::Second<f64>) {
+            self.v = self.drive + (self.v - self.drive) * (-*(dt / self.tau)).exp();
+        }
+
+        fn receive(&mut self, input: si::Volt<f64>) {
+            self.v += input;
+        }
+
+        fn reset(&mut self) {
+            self.v = 0.0 * si::V;
+        }
+
+        fn next_spike_in(&self) -> Option<si::Second<f64>> {
+            if self.drive > self.threshold {
+                Some(self.tau * (*((self.drive - self.v) / (self.drive - self.threshold))).ln())
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn event_driven_network() {
+        let lif = |drive: f64| ExactLif {
+            v: 0.0 * si::V,
+            drive: drive * si::V,
+            tau: 1.0 * si::S,
+            threshold: 1.0 * si::V,
+        };
+        let mut network = EventDrivenNetwork::new(vec![lif(2.0), lif(0.0), lif(0.0)]);
+        network.connect(0, 1, 1.5, 0.1 * si::S);
+        network.schedule_input(0.5 * si::S, 2, 0.5 * si::V);
+        network.run_until(3.0 * si::S);
+
+        let period = std::f64::consts::LN_2;
+        let mut expected = Vec::new();
+        for k in 1..=4 {
+            expected.push((k as f64 * period, 0));
+            expected.push((k as f64 * period + 0.1, 1));
+        }
+        let spikes = network.spikes();
+        assert_eq!(spikes.len(), expected.len());
+        for (&(time, neuron), &(expected_time, expected_neuron)) in
+            spikes.iter().zip(expected.iter())
+        {
+            assert_eq!(neuron, expected_neuron);
+            assert!((*(time / si::S) - expected_time).abs() < 1e-9);
+        }
+
+        // The third neuron is only advanced when it is looked at.
+        let v = network.neuron(2).get_voltage();
+        assert!((*(v / si::V) - 0.5 * (-2.5f64).exp()).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot run until a time in the past")]
+    fn event_driven_network_does_not_run_backwards() {
+        let mut network = EventDrivenNetwork::<ExactLif, _, _>::new(Vec::new());
+        network.run_until(1.0 * si::S);
+        network.run_until(0.5 * si::S);
+    }
+
+    #[test]
+    fn scheduler_interleaves_clocks() {
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut scheduler = Scheduler::new();
+        scheduler.add(0.1 * si::S, Slot::Groups, 0, |_| {
+            log.borrow_mut().push("fast")
+        });
+        scheduler.add(0.3 * si::S, Slot::End, 0, |_| {
+            log.borrow_mut().push("monitor")
+        });
+        scheduler.add(0.3 * si::S, Slot::Start, 1, |_| {
+            log.borrow_mut().push("late input")
+        });
+        scheduler.add(0.3 * si::S, Slot::Start, 0, |_| {
+            log.borrow_mut().push("input")
+        });
+        scheduler.run(0.6 * si::S);
+        drop(scheduler);
+
+        let tick = vec!["input", "late input", "fast", "monitor", "fast", "fast"];
+        assert_eq!(*log.borrow(), [tick.clone(), tick].concat());
+    }
+
+    #[test]
+    fn scheduler_runs_network() {
+        let make_network = || {
+            let synapse = StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.1);
+            NetworkBuilder::new(vec![constant_after(0.5, 20.0)])
+                .layer(1, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+                .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+                .build()
+        };
+        let mut expected = make_network();
+        expected.run(30.0 * si::S, 0.1 * si::S);
+
+        let network = std::cell::RefCell::new(make_network());
+        let mut recorded = Vec::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(0.1 * si::S, Slot::Groups, 0, |dt| {
+            network.borrow_mut().step(dt)
+        });
+        scheduler.add(1.0 * si::S, Slot::End, 0, |_| {
+            recorded.push(network.borrow().voltages(1)[0])
+        });
+        scheduler.run(30.0 * si::S);
+        assert_eq!(scheduler.time(), 30.0 * si::S);
+        drop(scheduler);
+
+        assert_eq!(recorded.len(), 30);
+        assert_eq!(network.borrow().voltages(1), expected.voltages(1));
+    }
+
+    /// A synapse that counts the presynaptic spikes and the steps it gets.
+    struct CountingSynapse {
+        pre_spikes: usize,
+        steps: usize,
+        elapsed: si::Second<f64>,
+    }
+
+    impl Synaptic<si::Volt<f64>, si::Second<f64>> for CountingSynapse {
+        fn on_pre(&mut self, _input: si::Volt<f64>) {
+            self.pre_spikes += 1;
+        }
+
+        fn on_post(&mut self, _input: si::Volt<f64>) {}
+
+        fn current_weight(&self) -> f64 {
+            1.0
+        }
+
+        fn advance_once(&mut self, dt: si::Second<f64>) {
+            self.steps += 1;
+            self.elapsed += dt;
+        }
+    }
+
+    #[test]
+    fn scheduler_runs_synapses_on_their_own_clock() {
+        let synapse = CountingSynapse {
+            pre_spikes: 0,
+            steps: 0,
+            elapsed: 0.0 * si::S,
+        };
+        let network = std::cell::RefCell::new(
+            NetworkBuilder::new(vec![SpikeAtTimes::new(
+                vec![0.25 * si::S, 0.5 * si::S, 0.75 * si::S],
+                0.005 * si::S,
+                1.0 * si::V,
+            )])
+            .layer(1, |_| InputProbe { input: 0.0 * si::V })
+            .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse)])
+            .build(),
+        );
+        let mut input_spikes = 0;
+        let mut scheduler = Scheduler::new();
+        scheduler.add(0.01 * si::S, Slot::Groups, 0, |dt| {
+            let mut network = network.borrow_mut();
+            network.step_neurons(dt);
+            if network.spikes(INPUT_LAYER)[0] {
+                input_spikes += 1;
+            }
+        });
+        scheduler.add(0.1 * si::S, Slot::Synapses, 0, |dt| {
+            network.borrow_mut().step_synapses(dt)
+        });
+        scheduler.run(1.0 * si::S);
+        drop(scheduler);
+
+        let mut network = network.into_inner();
+        assert!(((network.time() - 1.0 * si::S) / si::S).abs() < 1e-9);
+        let (_, _, synapse) = network.connections(INPUT_LAYER, 1).next().unwrap();
+        // Spikes are handled at the resolution of the neurons, but the
+        // synapses only take the ten steps of their own clock.
+        assert!(input_spikes > 0);
+        assert_eq!(synapse.pre_spikes, input_spikes);
+        assert_eq!(synapse.steps, 10);
+        assert!(((synapse.elapsed - 1.0 * si::S) / si::S).abs() < 1e-9);
+    }
+
+    #[test]
+    fn neuron_group_matches_neurons() {
+        let make_neuron = |i: usize| Izikhevich::new(0.02, 0.2, -65.0 + i as f64, 8.0);
+        let mut neurons: Vec<Izikhevich> = (0..4).map(make_neuron).collect();
+        let mut group = IzikhevichGroup::new((0..4).map(make_neuron).collect());
+        assert_eq!(group.len(), 4);
+
+        let mut spike_count = 0;
+        for step in 0..300 {
+            let inputs: Vec<si::Volt<f64>> = (0..4)
+                .map(|i| (20.0 + ((step + i) % 3) as f64) * si::V)
+                .collect();
+            let spiked = group.handle_inputs(&inputs, 0.1 * si::S);
+            let mut expected = Vec::new();
+            for (i, (neuron, &input)) in neurons.iter_mut().zip(inputs.iter()).enumerate() {
+                neuron.handle_input(input, 0.1 * si::S);
+                if neuron.did_spike() {
+                    expected.push(i);
+                }
+                assert_eq!(group.get_voltage(i), neuron.get_voltage());
+                assert_eq!(group.did_spike(i), neuron.did_spike());
+            }
+            assert_eq!(spiked, expected);
+            spike_count += spiked.len();
+        }
+        assert!(spike_count > 0);
+    }
+
+    // Forward Euler on a harmonic oscillator, which needs both equations to
+    // read the old state.
+    brian_rs_macros::define_neuron! {
+    Oscillator<si::Volt<f64>, si::Second<f64>>:
+    params { x0: f64 }
+    initialize {
+        x: si::Volt<f64> = x0 * si::V;
+        y: si::Volt<f64> = 0.0 * si::V
+    }
+    time_step {
+        x @ = -self.y / si::S;
+        y @ = (self.x + input) / si::S
+    }
+    spike_when { false }
+    get_voltage { self.y }
+    reset { self.x = self.x0 * si::V }
+    }
+
+    // Symplectic Euler on the same oscillator, reading the new `x`.
+    brian_rs_macros::define_neuron! {
+    SymplecticOscillator<si::Volt<f64>, si::Second<f64>>:
+    params { x0: f64 }
+    initialize {
+        x: si::Volt<f64> = x0 * si::V;
+        y: si::Volt<f64> = 0.0 * si::V
+    }
+    time_step {
+        x @ = -self.y / si::S;
+        y @ = (~x + input) / si::S
+    }
+    spike_when { false }
+    get_voltage { self.y }
+    reset { self.x = self.x0 * si::V }
+    }
+
+    #[test]
+    fn equations_update_simultaneously() {
+        let mut simultaneous = Oscillator::new(1.0);
+        let mut symplectic = SymplecticOscillator::new(1.0);
+        for _ in 0..2 {
+            simultaneous.handle_input(0.0 * si::V, 0.1 * si::S);
+            symplectic.handle_input(0.0 * si::V, 0.1 * si::S);
+        }
+        // y2 = y1 + x1 * dt with the old x1 = 1, but the new x2 = 0.99 for `~x`.
+        assert!(((simultaneous.get_voltage() - 0.2 * si::V) / si::V).abs() < 1e-12);
+        assert!(((symplectic.get_voltage() - 0.199 * si::V) / si::V).abs() < 1e-12);
+    }
+
+    // The logistic equation x' = x (1 - x), with y' = x to check that the
+    // variables are integrated together.
+    macro_rules! logistic_neuron {
+        ($name:ident, $method:ident) => {
+            brian_rs_macros::define_neuron! {
+            $name<si::Volt<f64>, si::Second<f64>>:
+            params { x0: f64 }
+            initialize {
+                x: si::Volt<f64> = x0 * si::V;
+                y: si::Volt<f64> = 0.0 * si::V
+            }
+            method { $method }
+            time_step {
+                x @ = (self.x * (1.0 - self.x / si::V) + input) / si::S;
+                y @ = self.x / si::S
+            }
+            spike_when { false }
+            get_voltage { self.x }
+            reset { self.x = self.x0 * si::V }
+            }
+
+            impl $name {
+                fn state(&self) -> (f64, f64) {
+                    (*(self.x / si::V), *(self.y / si::V))
+                }
+            }
+        };
+    }
+
+    logistic_neuron!(LogisticEuler, euler);
+    logistic_neuron!(LogisticRk2, rk2);
+    logistic_neuron!(LogisticRk4, rk4);
+    logistic_neuron!(LogisticExponentialEuler, exponential_euler);
+    logistic_neuron!(LogisticBackwardEuler, backward_euler);
+
+    /// Estimates the order of convergence of a method from its errors at
+    /// t = 1 s with 20 and 40 steps, against x(t) = x0 e^t / (1 - x0 + x0 e^t)
+    /// and y(t) = ln(1 - x0 + x0 e^t).
+    fn logistic_convergence_order<N: InnerSpikeGenerator<si::Volt<f64>, si::Second<f64>>>(
+        new: fn(f64) -> N,
+        state: fn(&N) -> (f64, f64),
+    ) -> f64 {
+        let x0 = 0.1;
+        let growth = 1.0 - x0 + x0 * 1f64.exp();
+        let (exact_x, exact_y) = (x0 * 1f64.exp() / growth, growth.ln());
+        let error = |steps: usize| {
+            let mut neuron = new(x0);
+            for _ in 0..steps {
+                neuron.handle_input(0.0 * si::V, (1.0 / steps as f64) * si::S);
+            }
+            let (x, y) = state(&neuron);
+            (x - exact_x).abs().max((y - exact_y).abs())
+        };
+        (error(20) / error(40)).log2()
+    }
+
+    #[test]
+    fn integrators_converge_at_their_order() {
+        let orders = [
+            (
+                logistic_convergence_order(LogisticEuler::new, LogisticEuler::state),
+                1.0,
+            ),
+            (
+                logistic_convergence_order(LogisticRk2::new, LogisticRk2::state),
+                2.0,
+            ),
+            (
+                logistic_convergence_order(LogisticRk4::new, LogisticRk4::state),
+                4.0,
+            ),
+            (
+                logistic_convergence_order(
+                    LogisticExponentialEuler::new,
+                    LogisticExponentialEuler::state,
+                ),
+                1.0,
+            ),
+            (
+                logistic_convergence_order(
+                    LogisticBackwardEuler::new,
+                    LogisticBackwardEuler::state,
+                ),
+                1.0,
+            ),
+        ];
+        for (order, expected) in orders.iter() {
+            assert!(
+                (order - expected).abs() < 0.2,
+                "Got order {}, expected {}",
+                order,
+                expected
+            );
+        }
+    }
+
+    // A synapse trace that decays much faster than the time-step, which is
+    // too stiff for Euler.
+    macro_rules! stiff_synapse {
+        ($name:ident, $method:ident) => {
+            brian_rs_macros::define_synapse! {
+            $name<si::Volt<f64>, si::Second<f64>>:
+            params { rate: f64 }
+            initialize { a: si::Unitless<f64> = 1.0 * si::S / si::S }
+            method { $method }
+            time_step { a @ = -self.rate * self.a / si::S }
+            weight_getter { *self.a }
+            on_pre { self.a += 1.0 }
+            on_post { self.a += 1.0 }
+            }
+        };
+    }
+
+    stiff_synapse!(StiffEuler, euler);
+    stiff_synapse!(StiffExponentialEuler, exponential_euler);
+    stiff_synapse!(StiffBackwardEuler, backward_euler);
+    stiff_synapse!(StiffExact, exact);
+
+    #[test]
+    fn integrators_for_stiff_equations() {
+        let mut euler = StiffEuler::new(1000.0);
+        let mut exponential = StiffExponentialEuler::new(1000.0);
+        let mut backward = StiffBackwardEuler::new(1000.0);
+        for _ in 0..10 {
+            euler.advance_once(0.01 * si::S);
+            exponential.advance_once(0.01 * si::S);
+            backward.advance_once(0.01 * si::S);
+        }
+        // Euler multiplies by 1 - 10 every step.
+        assert!(euler.current_weight().abs() > 1e9);
+        // Exponential Euler is exact for linear equations.
+        assert!((exponential.current_weight() - (-100f64).exp()).abs() < 1e-50);
+        // Backward Euler divides by 1 + 10 every step.
+        assert!((backward.current_weight() - 11f64.powi(-10)).abs() < 1e-20);
+    }
+
+    // Two traces that relax towards each other much faster than they decay,
+    // so that backward Euler has to solve for both at once.
+    brian_rs_macros::define_synapse! {
+    CoupledBackwardEuler<si::Volt<f64>, si::Second<f64>>:
+    params { rate: f64 }
+    initialize {
+        a: si::Unitless<f64> = 1.0 * si::S / si::S;
+        b: si::Unitless<f64> = 0.0 * si::S / si::S
+    }
+    method { backward_euler }
+    time_step {
+        a @ = self.rate * (self.b - self.a) / si::S - self.a / si::S;
+        b @ = self.rate * (self.a - self.b) / si::S - self.b / si::S
+    }
+    weight_getter { *self.a }
+    on_pre { self.a += 1.0 }
+    on_post { self.b += 1.0 }
+    }
+
+    #[test]
+    fn backward_euler_solves_coupled_equations() {
+        let (rate, dt) = (1000.0, 0.01);
+        let mut synapse = CoupledBackwardEuler::new(rate);
+        // Every step solves (1 - A dt) y = x, with A = [-r - 1, r; r, -r - 1].
+        let (diagonal, off_diagonal) = (1.0 + (rate + 1.0) * dt, -rate * dt);
+        let det = diagonal * diagonal - off_diagonal * off_diagonal;
+        let (mut a, mut b) = (1.0, 0.0);
+        for _ in 0..10 {
+            synapse.advance_once(dt * si::S);
+            let next_a = (diagonal * a - off_diagonal * b) / det;
+            b = (diagonal * b - off_diagonal * a) / det;
+            a = next_a;
+            assert!((*synapse.a - a).abs() <= 1e-12 * a.abs());
+            assert!((*synapse.b - b).abs() <= 1e-12 * b.abs());
+        }
+    }
+
+    // y = 1 + y^2 has no real solution, so a step of 1 s cannot converge.
+    brian_rs_macros::define_synapse! {
+    Runaway<si::Volt<f64>, si::Second<f64>>:
+    params { rate: f64 }
+    initialize { a: si::Unitless<f64> = 1.0 * si::S / si::S }
+    method { backward_euler }
+    time_step { a @ = self.rate * self.a * self.a / si::S }
+    weight_getter { *self.a }
+    on_pre { self.a += 1.0 }
+    on_post { self.a += 1.0 }
+    }
+
+    #[test]
+    #[should_panic(expected = "Backward Euler did not converge")]
+    fn backward_euler_panics_without_a_solution() {
+        Runaway::new(1.0).advance_once(1.0 * si::S);
+    }
+
+    // A leaky membrane driven by an exponentially decaying synaptic current,
+    // which is linear but coupled.
+    brian_rs_macros::define_neuron! {
+    SynapticLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau_m: f64, tau_s: f64 }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        g: si::Volt<f64> = 2.0 * si::V
+    }
+    method { exact }
+    time_step {
+        v @ = (input - self.v + self.g) / (self.tau_m * si::S);
+        g @ = -self.g / (self.tau_s * si::S)
+    }
+    spike_when { false }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    #[test]
+    fn exact_integration_does_not_depend_on_dt() {
+        let (tau_m, tau_s, input) = (0.02, 0.005, 1.0);
+        // v = I + (v0 - I - C) e^(-t / tau_m) + C e^(-t / tau_s) with
+        // C = g0 tau_s / (tau_s - tau_m).
+        let c = 2.0 * tau_s / (tau_s - tau_m);
+        let exact_v = |t: f64| input + (-input - c) * (-t / tau_m).exp() + c * (-t / tau_s).exp();
+        for &(dt, steps) in [(0.001, 100), (0.01, 10), (0.05, 2), (0.1, 1)].iter() {
+            let mut neuron = SynapticLif::new(tau_m, tau_s);
+            for _ in 0..steps {
+                neuron.handle_input(input * si::V, dt * si::S);
+            }
+            let v = *(neuron.get_voltage() / si::V);
+            assert!((v - exact_v(dt * steps as f64)).abs() < 1e-12);
+        }
+
+        let mut stiff = StiffExact::new(1000.0);
+        stiff.advance_once(0.1 * si::S);
+        assert!((stiff.current_weight() / (-100f64).exp() - 1.0).abs() < 1e-10);
+    }
+
+    brian_rs_macros::define_neuron! {
+    ExactRefractoryLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    method { exact }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) (unless refractory) }
+    spike_when { self.v > self.threshold * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    refractory { 0.005 * si::S }
+    }
+
+    #[test]
+    fn exact_integration_holds_refractory_groups() {
+        let (tau, threshold, input, dt) = (0.01, 1.0, 2.0, 0.001);
+        let mut neuron = ExactRefractoryLif::new(tau, threshold);
+        let mut group = ExactRefractoryLifGroup::new(vec![
+            ExactRefractoryLif::new(tau, threshold),
+            ExactRefractoryLif::new(tau, 10.0),
+        ]);
+        // The steps since the last reset, of which the first four are
+        // still refractory. The neuron starts out charging.
+        let mut since_reset = 4;
+        for _ in 0..100 {
+            let spiked = neuron.did_spike();
+            neuron.handle_input(input * si::V, dt * si::S);
+            group.handle_inputs(&[input * si::V, input * si::V], dt * si::S);
+            assert_eq!(group.get_voltage(0), neuron.get_voltage());
+            since_reset = if spiked { 0 } else { since_reset + 1 };
+            // v = I (1 - e^(-t / tau)) once the 5 ms refractory period is over.
+            let charging = (since_reset as f64 - 4.0).max(0.0) * dt;
+            let v = input * (1.0 - (-charging / tau).exp());
+            assert!((*(neuron.get_voltage() / si::V) - v).abs() < 1e-12);
+        }
+    }
+
+    brian_rs_macros::define_neuron! {
+    Lif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v > self.threshold * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    #[test]
+    fn adaptive_integration_finds_spike_times() {
+        let (tau, threshold, input): (f64, f64, f64) = (0.01, 1.0, 2.0);
+        let resolution = 1e-7;
+        let period = tau * (input / (input - threshold)).ln();
+        let mut neuron = Lif::new(tau, threshold);
+        let spikes = neuron.advance_adaptive(input * si::V, 0.05 * si::S, 1e-8, resolution * si::S);
+        assert_eq!(spikes.len(), (0.05 / period) as usize);
+        let mut last = 0.0;
+        for spike in spikes {
+            let time = *(spike / si::S);
+            assert!((time - last - period).abs() < 2.0 * resolution);
+            last = time;
+        }
+
+        // A resolution finer than the shortest step stops at the shortest
+        // step.
+        let mut neuron = Lif::new(tau, threshold);
+        let spikes = neuron.advance_adaptive(input * si::V, 0.05 * si::S, 1e-8, 1e-300 * si::S);
+        assert_eq!(spikes.len(), (0.05 / period) as usize);
+    }
+
+    #[test]
+    #[should_panic(expected = "The spike resolution must be positive")]
+    fn adaptive_integration_needs_a_positive_resolution() {
+        let mut neuron = Lif::new(0.01, 1.0);
+        neuron.advance_adaptive(2.0 * si::V, 0.05 * si::S, 1e-8, 0.0 * si::S);
+    }
+
+    brian_rs_macros::define_neuron! {
+    RefractoryLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) (unless refractory) }
+    spike_when { self.v > self.threshold * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    refractory { 0.005 * si::S }
+    }
+
+    #[test]
+    fn refractory_neurons_hold_and_do_not_spike() {
+        let (tau, threshold, input): (f64, f64, f64) = (0.01, 1.0, 2.0);
+        let dt = 0.0001 * si::S;
+        let mut neuron = RefractoryLif::new(tau, threshold);
+        while !neuron.did_spike() {
+            neuron.handle_input(input * si::V, dt);
+        }
+        // The step with the reset and the next 49 are refractory.
+        for _ in 0..50 {
+            neuron.handle_input(input * si::V, dt);
+            assert_eq!(neuron.get_voltage(), 0.0 * si::V);
+        }
+        neuron.handle_input(input * si::V, dt);
+        assert!(neuron.get_voltage() > 0.0 * si::V);
+
+        let resolution = 1e-7;
+        let period = tau * (input / (input - threshold)).ln();
+        let interval = 0.005 + period;
+        let mut neuron = RefractoryLif::new(tau, threshold);
+        let spikes = neuron.advance_adaptive(input * si::V, 0.05 * si::S, 1e-8, resolution * si::S);
+        assert_eq!(spikes.len(), 1 + ((0.05 - period) / interval) as usize);
+        for pair in spikes.windows(2) {
+            let gap = *((pair[1] - pair[0]) / si::S);
+            assert!((gap - interval).abs() < 2.0 * resolution);
+        }
+    }
+
+    // An Ornstein-Uhlenbeck membrane, whose stationary variance is
+    // sigma^2 tau / 2.
+    brian_rs_macros::define_neuron! {
+    NoisyMembrane<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, sigma: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) + self.sigma * xi * si::V / si::S }
+    spike_when { false }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    #[test]
+    fn noise_is_reproducible_and_has_the_right_variance() {
+        let (tau, sigma, dt) = (0.01, 1.0, 0.0001 * si::S);
+        let mut first = NoisyMembrane::new(tau, sigma, Noise::new(7));
+        let mut second = NoisyMembrane::new(tau, sigma, Noise::new(7));
+        let mut other = NoisyMembrane::new(tau, sigma, Noise::new(8));
+        let mut group = NoisyMembraneGroup::new(vec![
+            NoisyMembrane::new(tau, sigma, Noise::new(7)),
+            NoisyMembrane::new(tau, sigma, Noise::new(8)),
+        ]);
+        let (mut sum, mut sum_squares, mut count) = (0.0, 0.0, 0.0);
+        for step in 0..200_000 {
+            first.handle_input(0.0 * si::V, dt);
+            second.handle_input(0.0 * si::V, dt);
+            other.handle_input(0.0 * si::V, dt);
+            group.handle_inputs(&[0.0 * si::V; 2], dt);
+            assert_eq!(first.get_voltage(), second.get_voltage());
+            assert_eq!(first.get_voltage(), group.get_voltage(0));
+            assert_eq!(other.get_voltage(), group.get_voltage(1));
+            // Skips the first few time constants, before the variance settles.
+            if step >= 1000 {
+                let v = *(first.get_voltage() / si::V);
+                sum += v;
+                sum_squares += v * v;
+                count += 1.0;
+            }
+        }
+        assert_ne!(first.get_voltage(), other.get_voltage());
+        let mean = sum / count;
+        let variance = sum_squares / count - mean * mean;
+        assert!(mean.abs() < 0.01);
+        assert!((variance / (sigma * sigma * tau / 2.0) - 1.0).abs() < 0.1);
+    }
+
+    // The same conductance-based membrane, with and without naming the
+    // synaptic current.
+    brian_rs_macros::define_neuron! {
+    NamedCurrent<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, e_syn: f64 }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        g: si::Unitless<f64> = 1.0 * si::S / si::S
+    }
+    expressions {
+        i_syn: si::Volt<f64> = self.g * (self.e_syn * si::V - self.v);
+        i_total: si::Volt<f64> = self.i_syn - self.v
+    }
+    method { rk4 }
+    time_step {
+        v @ = (input + self.i_total) / (self.tau * si::S);
+        g @ = -self.g / (0.005 * si::S)
+    }
+    spike_when { self.i_syn < 0.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    brian_rs_macros::define_neuron! {
+    InlineCurrent<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, e_syn: f64 }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        g: si::Unitless<f64> = 1.0 * si::S / si::S
+    }
+    method { rk4 }
+    time_step {
+        v @ = (input + self.g * (self.e_syn * si::V - self.v) - self.v) / (self.tau * si::S);
+        g @ = -self.g / (0.005 * si::S)
+    }
+    spike_when { self.g * (self.e_syn * si::V - self.v) < 0.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    #[test]
+    fn expressions_are_evaluated_with_the_state() {
+        let (tau, e_syn, dt) = (0.01, 2.0, 0.001 * si::S);
+        let mut named = NamedCurrent::new(tau, e_syn);
+        let mut inline = InlineCurrent::new(tau, e_syn);
+        let mut group = NamedCurrentGroup::new(vec![NamedCurrent::new(tau, e_syn)]);
+        for _ in 0..50 {
+            named.handle_input(0.5 * si::V, dt);
+            inline.handle_input(0.5 * si::V, dt);
+            group.handle_inputs(&[0.5 * si::V], dt);
+            assert_eq!(named.get_voltage(), inline.get_voltage());
+            assert_eq!(named.did_spike(), inline.did_spike());
+            assert_eq!(named.i_syn(), named.g * (e_syn * si::V - named.v));
+            assert_eq!(named.i_total(), named.i_syn() - named.v);
+            assert_eq!(group.i_syn(0), named.i_syn());
+        }
+    }
+
+    brian_rs_macros::define_neuron! {
+    ResetWithExpression<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64 }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        w: si::Volt<f64> = 0.0 * si::V
+    }
+    expressions { half_v: si::Volt<f64> = self.v * 0.5 }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v > 1.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V; self.w += self.half_v }
+    }
+
+    #[test]
+    fn resets_read_expressions_before_changing_the_state() {
+        let mut neuron = ResetWithExpression::new(0.01);
+        while !neuron.did_spike() {
+            neuron.handle_input(2.0 * si::V, 0.001 * si::S);
+        }
+        let half_v = neuron.half_v();
+        neuron.handle_input(2.0 * si::V, 0.001 * si::S);
+        assert_eq!(neuron.w, half_v);
+    }
+
+    brian_rs_macros::define_neuron! {
+    BalancedLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    inputs { exc: si::Volt<f64>, inh: si::Volt<f64> }
+    time_step { v @ = (input.exc - input.inh - self.v) / (self.tau * si::S) }
+    spike_when { self.v > 100.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    #[test]
+    fn synapses_can_feed_different_ports() {
+        let mut neuron = BalancedLif::new(0.01);
+        assert_eq!(neuron.ports(), ["exc", "inh"]);
+        neuron.handle_port_inputs(&[1.0 * si::V, 1.0 * si::V], 0.001 * si::S);
+        assert_eq!(neuron.get_voltage(), 0.0 * si::V);
+        let mut first_port = BalancedLif::new(0.01);
+        neuron.handle_input(1.0 * si::V, 0.001 * si::S);
+        first_port.handle_named_inputs(
+            BalancedLifInputs {
+                exc: 1.0 * si::V,
+                inh: 0.0 * si::V,
+            },
+            0.001 * si::S,
+        );
+        assert_eq!(neuron.get_voltage(), first_port.get_voltage());
+
+        let network = |inhibit: bool| {
+            let synapse = || StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0);
+            let builder =
+                NetworkBuilder::new(vec![constant_after(0.5, 1.0), constant_after(0.5, 1.0)])
+                    .layer(1, |_| BalancedLif::new(0.01))
+                    .connect(INPUT_LAYER, 1, vec![Connection::new(0, 0, synapse())])
+                    .connect(INPUT_LAYER, 1, vec![Connection::new(1, 0, synapse())]);
+            let mut network = if inhibit {
+                builder.to_port("inh")
+            } else {
+                builder
+            }
+            .build();
+            network.run(1.0 * si::S, 0.01 * si::S);
+            network.voltages(1)[0]
+        };
+        assert_eq!(network(true), 0.0 * si::V);
+        assert!(network(false) > 1.0 * si::V);
+    }
+
+    brian_rs_macros::define_neuron! {
+    GenericLif<V: Quantity<F>, T: Quantity<F>, F: Float>:
+    params { tau: T, v_rest: V, threshold: V }
+    initialize { v: V = v_rest }
+    method { rk4 }
+    time_step { v @ = (input + self.v_rest - self.v) / self.tau (unless refractory) }
+    spike_when { self.v > self.threshold }
+    get_voltage { self.v }
+    reset { self.v = self.v_rest }
+    refractory { self.tau * F::from_f64(0.5) }
+    }
+
+    #[test]
+    fn generic_neurons_work_with_any_units_and_float() {
+        let mut si_f64: GenericLif<si::Volt<f64>, si::Second<f64>, f64> =
+            GenericLif::new(0.01 * si::S, 0.0 * si::V, 0.5 * si::V);
+        let (s32, v32) = (si::f32consts::S, si::f32consts::V);
+        let mut si_f32: GenericLif<si::Volt<f32>, si::Second<f32>, f32> =
+            GenericLif::new(0.01 * s32, 0.0 * v32, 0.5 * v32);
+        let mut unitless: GenericLif<f64, f64, f64> = GenericLif::new(0.01, 0.0, 0.5);
+        let mut spikes = [0, 0, 0];
+        for _ in 0..1000 {
+            si_f64.handle_input(1.0 * si::V, 0.0001 * si::S);
+            si_f32.handle_input(1.0 * v32, 0.0001 * s32);
+            unitless.handle_input(1.0, 0.0001);
+            for (count, spiked) in spikes.iter_mut().zip([
+                si_f64.did_spike(),
+                si_f32.did_spike(),
+                unitless.did_spike(),
+            ]) {
+                *count += spiked as usize;
+            }
+            assert_eq!(*(si_f64.get_voltage() / si::V), unitless.get_voltage());
+            assert!((*(si_f32.get_voltage() / v32) as f64 - unitless.get_voltage()).abs() < 1e-4);
+        }
+        assert!(spikes[0] > 0);
+        assert_eq!(spikes, [spikes[0]; 3]);
+    }
+
+    brian_rs_macros::define_synapse! {
+    GenericTrace<V: Quantity<F>, T: Quantity<F>, F: Float>:
+    params { tau: T }
+    initialize { trace: F = F::from_f64(1.0) }
+    method { rk2 }
+    time_step { trace @ = -self.trace / self.tau }
+    weight_getter { self.trace.to_f64() }
+    on_pre { self.trace = F::from_f64(1.0) }
+    on_post { self.trace = F::from_f64(0.0) }
+    }
+
+    #[test]
+    fn generic_synapses_decay_in_any_float() {
+        let mut unitless_f64: GenericTrace<f64, f64, f64> = GenericTrace::new(0.01);
+        let mut unitless_f32: GenericTrace<f32, f32, f32> = GenericTrace::new(0.01);
+        for _ in 0..100 {
+            unitless_f64.advance_once(0.0001);
+            unitless_f32.advance_once(0.0001);
+        }
+        // Each midpoint step scales the trace by 1 - h + h^2 / 2, for h = dt / tau.
+        let expected = (1.0f64 - 0.01 + 0.00005).powi(100);
+        assert!((unitless_f64.current_weight() - expected).abs() < 1e-12);
+        assert!((unitless_f32.current_weight() - expected).abs() < 1e-5);
+        unitless_f32.on_post(1.0);
+        assert_eq!(unitless_f32.current_weight(), 0.0);
+    }
+
+    brian_rs_macros::define_neuron! {
+    #[reflect]
+    ReflectedLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v > self.threshold * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    }
+
+    brian_rs_macros::define_synapse! {
+    #[reflect]
+    ReflectedTrace<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, w: si::Unitless<f64> }
+    initialize { trace: si::Unitless<f64> = 0.0 * si::S / si::S }
+    time_step { trace @ = -(self.trace / self.tau) / si::S }
+    weight_getter { *(self.w + self.trace) }
+    on_pre { self.trace += 1.0 }
+    on_post { self.trace = 0.0 * si::S / si::S }
+    }
+
+    brian_rs_macros::define_neuron! {
+    #[reflect]
+    ReflectedGenericLif<V: Quantity<F>, T: Quantity<F>, F: Float>:
+    params { tau: T, v_rest: V, threshold: V }
+    initialize { v: V = v_rest }
+    time_step { v @ = (input + self.v_rest - self.v) / self.tau }
+    spike_when { self.v > self.threshold }
+    get_voltage { self.v }
+    reset { self.v = self.v_rest }
+    }
+
+    #[test]
+    fn variables_can_be_read_and_written_by_name() {
+        let mut neuron = ReflectedLif::new(0.01, 1.0);
+        assert_eq!(neuron.params(), ["tau", "threshold"]);
+        assert_eq!(neuron.state_vars(), ["v"]);
+        assert_eq!(neuron.units("v"), "m^2*kg*s^-3*A^-1");
+        assert_eq!(neuron.units("tau"), "");
+        neuron.set_var("v", 0.25);
+        assert_eq!(neuron.get_voltage(), 0.25 * si::V);
+        neuron.set_var("threshold", 0.2);
+        assert!(neuron.did_spike());
+        assert_eq!(neuron.get_var("threshold"), 0.2);
+
+        let mut synapse = ReflectedTrace::new(0.01, 1.0 * si::S / si::S);
+        assert_eq!(synapse.state_vars(), ["trace"]);
+        assert_eq!(synapse.units("w"), "");
+        synapse.set_var("w", 2.0);
+        assert_eq!(synapse.current_weight(), 2.0);
+
+        let mut generic: ReflectedGenericLif<si::Volt<f32>, si::Second<f32>, f32> =
+            ReflectedGenericLif::new(
+                0.01 * si::f32consts::S,
+                0.0 * si::f32consts::V,
+                0.5 * si::f32consts::V,
+            );
+        assert_eq!(generic.units("tau"), "s");
+        generic.set_var("v", 0.125);
+        assert_eq!(generic.get_var("v"), 0.125);
+        assert_eq!(
+            <si::Volt<f32> as Magnitude>::from_magnitude(0.125),
+            generic.get_voltage()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "`w` is not a variable of `ReflectedLif`")]
+    fn unknown_variables_panic() {
+        ReflectedLif::new(0.01, 1.0).get_var("w");
+    }
+
+    // Without `#[reflect]`, variables need not be magnitudes.
+    brian_rs_macros::define_neuron! {
+    LimitedLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64, max_spikes: usize }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        spikes: usize = 0;
+        exhausted: bool = false
+    }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v > self.threshold * si::V && self.spikes < self.max_spikes }
+    get_voltage { self.v }
+    reset {
+        self.v = 0.0 * si::V;
+        self.spikes += 1;
+        self.exhausted = self.spikes == self.max_spikes
+    }
+    }
+
+    #[test]
+    fn models_without_reflection_can_have_any_variables() {
+        let mut neuron = LimitedLif::new(0.01, 1.0, 3);
+        let mut spike_count = 0;
+        for _ in 0..1000 {
+            neuron.handle_input(2.0 * si::V, 0.0001 * si::S);
+            if neuron.did_spike() {
+                spike_count += 1;
+            }
+        }
+        assert_eq!(spike_count, 3);
+        assert!(neuron.exhausted);
+    }
+
+    brian_rs_macros::define_input_generator! {
+    Pacemaker<si::Volt<f64>, si::Second<f64>>:
+    params { period: f64, amplitude: f64 }
+    initialize { phase: si::Unitless<f64> = 0.0 * si::S / si::S }
+    time_step { phase @ = 1.0 / (self.period * si::S) }
+    spike_when { *self.phase >= 1.0 }
+    get_voltage { self.amplitude * si::V }
+    reset { self.phase = 0.0 * si::S / si::S }
+    }
+
+    brian_rs_macros::define_input_generator! {
+    SineInput<si::Volt<f64>, si::Second<f64>>:
+    params { omega: f64 }
+    initialize {
+        x: si::Volt<f64> = 1.0 * si::V;
+        y: si::Volt<f64> = 0.0 * si::V
+    }
+    method { rk4 }
+    time_step {
+        x @ = -self.omega * self.y / si::S;
+        y @ = self.omega * self.x / si::S
+    }
+    spike_when { false }
+    get_voltage { self.x }
+    reset { self.x = 1.0 * si::V }
+    }
+
+    #[test]
+    fn input_generators_advance_on_their_own() {
+        let mut pacemaker = Pacemaker::new(0.008, 2.0);
+        let mut group =
+            PacemakerGroup::new(vec![Pacemaker::new(0.008, 2.0), Pacemaker::new(0.004, 2.0)]);
+        let mut spikes = [0; 3];
+        for _ in 0..80 {
+            pacemaker.advance(0.001 * si::S);
+            spikes[0] += pacemaker.did_spike() as usize;
+            for idx in group.advance(0.001 * si::S) {
+                spikes[idx + 1] += 1;
+            }
+        }
+        assert_eq!(spikes, [10, 10, 20]);
+        assert_eq!(pacemaker.get_voltage(), 2.0 * si::V);
+
+        let omega = 2.0 * std::f64::consts::PI;
+        let mut sine = SineInput::new(omega);
+        for step in 1..=1000 {
+            sine.advance(0.001 * si::S);
+            let expected = (omega * step as f64 * 0.001).cos();
+            assert!((*(sine.get_voltage() / si::V) - expected).abs() < 1e-9);
+        }
+    }
+
+    brian_rs_macros::define_network! {
+    Relay<si::Volt<f64>, si::Second<f64>>:
+    params { bump: f64 }
+    inputs { drive: ConstantAfterSpike[2] = constant_after(0.5, 20.0) }
+    populations { hidden: Izikhevich[2](0.02, 0.2, -65.0, 8.0) }
+    projections {
+        feed: StdpNeuron[drive -> hidden, rule: Rule::OneToOne](10.0, 10.0, 1.0 * si::S / si::S, bump)
+    }
+    monitors { spikes(hidden), voltages(hidden) }
+    }
+
+    // The generated code names everything it needs by its full path, so only
+    // the types of the parts of the network have to be in scope.
+    mod scoped {
+        use super::{constant_after, si, ConstantAfterSpike, Izikhevich, StdpNeuron};
+
+        brian_rs_macros::define_network! {
+        Scoped<si::Volt<f64>, si::Second<f64>>:
+        inputs { drive: ConstantAfterSpike[2] = constant_after(0.5, 20.0) }
+        populations { hidden: Izikhevich[3](0.02, 0.2, -65.0, 8.0) }
+        projections {
+            feed: StdpNeuron[drive -> hidden, delay: 0.2 * si::S](10.0, 10.0, 1.0 * si::S / si::S, 0.1)
+        }
+        monitors { spikes(hidden), voltages(hidden) }
+        }
+
+        #[test]
+        fn defined_networks_step_in_parallel_as_in_sequence() {
+            let mut sequential = Scoped::new(0.1 * si::S);
+            let mut parallel = Scoped::new(0.1 * si::S);
+            sequential.run(30.0 * si::S);
+            parallel.par_run(30.0 * si::S);
+            assert!(!sequential.hidden_spikes().is_empty());
+            assert_eq!(sequential.hidden_spikes(), parallel.hidden_spikes());
+            assert_eq!(sequential.hidden_voltages(), parallel.hidden_voltages());
+        }
+    }
+
+    #[test]
+    fn defined_network_matches_builder() {
+        let mut defined = Relay::new(0.1 * si::S, 0.1);
+        let mut built =
+            NetworkBuilder::new(vec![constant_after(0.5, 20.0), constant_after(0.5, 20.0)])
+                .layer(2, |_| Izikhevich::new(0.02, 0.2, -65.0, 8.0))
+                .connect_with(INPUT_LAYER, 1, &Rule::OneToOne, 0, |_, _| {
+                    StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.1)
+                })
+                .build();
+        let mut spikes = Vec::new();
+        for step in 0..300 {
+            defined.step();
+            built.step(0.1 * si::S);
+            assert_eq!(defined.hidden_voltages()[step], built.voltages(1));
+            for (idx, _) in built.spikes(1).iter().enumerate().filter(|(_, &s)| s) {
+                spikes.push((built.time() - 0.1 * si::S, idx));
+            }
+        }
+        assert!(!spikes.is_empty());
+        assert_eq!(defined.hidden_spikes(), &spikes[..]);
+        assert_eq!(defined.time(), built.time());
+
+        defined.feed.flush();
+        let weights: Vec<f64> = defined
+            .feed
+            .iter()
+            .map(|(_, _, s)| s.current_weight())
+            .collect();
+        let expected: Vec<f64> = built
+            .connections(INPUT_LAYER, 1)
+            .map(|(_, _, s)| s.current_weight())
+            .collect();
+        assert_eq!(weights, expected);
+        assert!(weights[0] > 1.0);
+    }
+
+    fn unit_synapse() -> StdpNeuron {
+        StdpNeuron::new(10.0, 10.0, 1.0 * si::S / si::S, 0.0)
+    }
+
+    brian_rs_macros::define_network! {
+    Mixed<si::Volt<f64>, si::Second<f64>>:
+    inputs { drive: ConstantAfterSpike[1] = constant_after(0.05, 1.0) }
+    populations {
+        now: BalancedLif[1](0.01),
+        later: BalancedLif[1](0.01),
+        balanced: BalancedLif[1](0.01),
+        lifs: Lif[2](0.01 * (i + 1) as f64, 100.0)
+    }
+    projections {
+        to_now: StdpNeuron[drive -> now] = unit_synapse(),
+        to_later: StdpNeuron[drive -> later, delay: 0.1 * si::S] = unit_synapse(),
+        excite: StdpNeuron[drive -> balanced] = unit_synapse(),
+        inhibit: StdpNeuron[drive -> balanced, port: inh] = unit_synapse(),
+        to_lifs: StdpNeuron[drive -> lifs] = unit_synapse()
+    }
+    monitors { voltages(now), voltages(later), voltages(balanced) }
+    }
+
+    #[test]
+    fn defined_network_mixes_types_ports_and_delays() {
+        let mut network = Mixed::new(0.01 * si::S);
+        network.run(0.5 * si::S);
+        assert_eq!(network.now_voltages().len(), 50);
+        let first_input = |voltages: &[Vec<si::Volt<f64>>]| {
+            voltages.iter().position(|v| v[0] != 0.0 * si::V).unwrap()
+        };
+        assert_eq!(
+            first_input(network.later_voltages()),
+            first_input(network.now_voltages()) + 10
+        );
+        assert!(network
+            .balanced_voltages()
+            .iter()
+            .all(|v| v[0] == 0.0 * si::V));
+        assert!(network.lifs[0].get_voltage() > network.lifs[1].get_voltage());
+    }
+
+    #[test]
+    fn models_describe_their_equations() {
+        let izhikevich = Izikhevich::to_latex();
+        assert!(izhikevich.starts_with("\\subsection*{Izikhevich}\n"));
+        assert!(izhikevich.contains(
+            "\\frac{d{u}}{dt} = \\frac{a \\cdot \\left(b \\cdot v - u\\right)}{\\mathrm{s}}"
+        ));
+        assert!(izhikevich.contains(
+            "\\paragraph{Reset}\n\\begin{gather*}\nv \\leftarrow c \\cdot \\mathrm{V} \\\\\nu \\leftarrow u + d \\cdot \\mathrm{V}\n\\end{gather*}"
+        ));
+
+        let stdp = StdpNeuron::describe();
+        assert!(stdp.starts_with("# StdpNeuron\n\nA synapse with voltages in `si::Volt<f64>`"));
+        assert!(stdp.contains("| $\\tau_{\\mathrm{pre}}$ | `f64` |\n"));
+        assert!(stdp.contains("## On presynaptic spike\n\n$$a_{\\mathrm{pre}} \\leftarrow"));
+
+        // Expressions are shown by name rather than inlined.
+        let named = NamedCurrent::describe();
+        assert!(named.contains("$$i_{\\mathrm{syn}} = g \\cdot \\left(e_{\\mathrm{syn}} \\cdot \\mathrm{V} - v\\right)$$"));
+        assert!(named.contains("## Spikes when\n\n$$i_{\\mathrm{syn}} < 0.0 \\cdot \\mathrm{V}$$"));
+
+        let refractory = RefractoryLif::describe();
+        assert!(refractory.contains("\\quad \\text{(unless refractory)}$$"));
+        assert!(refractory.contains("## Refractory period\n\n$$0.005 \\cdot \\mathrm{s}$$"));
+        assert!(SymplecticOscillator::describe().contains("\\tilde{x}"));
+        assert!(NoisyMembrane::describe().contains("\\sigma \\cdot \\xi"));
+        assert!(Pacemaker::describe().contains("\n\nAn input generator with voltages in"));
+    }
 }
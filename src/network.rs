@@ -0,0 +1,704 @@
+//! This module wires spike generators and synapses together into SNNs.
+//!
+//! A network is a stack of layers (populations of neurons). Layer 0 is always
+//! the input layer, made of `InputSpikeGenerator`s, and every layer after it
+//! holds `InnerSpikeGenerator`s. Layers are joined by projections, which are
+//! groups of synapses from neurons in one layer to neurons in another.
+//!
+//! Networks are put together with a `NetworkBuilder`. A built network can be
+//! wrapped into an `InputSnn` so that it can be used as an input neuron itself,
+//! or into a `MiddleSnn` so that it can be used as a hidden neuron.
+
+extern crate dimensioned as dim;
+
+use dim::si;
+use std::marker::PhantomData;
+use std::ops::{Add, AddAssign, Mul};
+
+use crate::connect::{self, Rule};
+use crate::parallel;
+use crate::spike_generators::{InnerSpikeGenerator, InputSpikeGenerator, SpikeGenerator};
+pub use crate::synapses::Connection;
+use crate::synapses::{SynapseGroup, Synaptic};
+
+/// The index of the input layer in a network.
+pub const INPUT_LAYER: usize = 0;
+
+/// What a network needs of the voltages its neurons carry. It is implemented
+/// for every type with the right operations, such as `si::Volt<f64>`.
+pub trait Voltage:
+    From<si::Volt<f64>> + Copy + PartialEq + Add<Output = Self> + Mul<f64, Output = Self>
+{
+}
+
+impl<X> Voltage for X where
+    X: From<si::Volt<f64>> + Copy + PartialEq + Add<Output = X> + Mul<f64, Output = X>
+{
+}
+
+/// What a network needs of the time-steps it is advanced by. It is
+/// implemented for every type with the right operations, such as
+/// `si::Second<f64>`.
+pub trait TimeStep:
+    From<si::Second<f64>> + Into<si::Second<f64>> + Copy + PartialEq + AddAssign
+{
+}
+
+impl<X> TimeStep for X where
+    X: From<si::Second<f64>> + Into<si::Second<f64>> + Copy + PartialEq + AddAssign
+{
+}
+
+/// `SynapseGroup::propagate` or `SynapseGroup::par_propagate`.
+type Propagate<S, V, T> = fn(&mut SynapseGroup<S, V, T>, &[V], &mut [V]);
+
+/// All the synapses going from one layer to another, into one of the input
+/// ports of the neurons of the target layer.
+struct Projection<S, V, T> {
+    from: usize,
+    to: usize,
+    port: usize,
+    synapses: SynapseGroup<S, V, T>,
+}
+
+/// An SNN with input neurons of type I, hidden neurons of type N and synapses
+/// of type S. The neurons carry voltages V and are advanced by time-steps T.
+///
+/// Every time-step, the input to a neuron is the sum of the voltages of its
+/// presynaptic neurons, weighted by the `current_weight` of each synapse. All
+/// the inputs are computed from the state at the start of the step, so the
+/// order of the layers does not matter. Neurons with several input ports get
+/// one such sum per port, and each projection feeds a single port: the first,
+/// unless it is routed elsewhere with `NetworkBuilder::to_port`.
+pub struct Network<I, N, S, V, T> {
+    input_layer: Vec<I>,
+    layers: Vec<Vec<N>>,
+    projections: Vec<Projection<S, V, T>>,
+    time: T,
+    _voltage: PhantomData<V>,
+}
+
+impl<I, N, S, V, T> Network<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    /// The number of layers, including the input layer.
+    pub fn num_layers(&self) -> usize {
+        self.layers.len() + 1
+    }
+
+    /// The number of neurons in the given layer.
+    ///
+    /// Panics if the layer does not exist.
+    pub fn layer_size(&self, layer: usize) -> usize {
+        if layer == INPUT_LAYER {
+            self.input_layer.len()
+        } else {
+            self.layer(layer).len()
+        }
+    }
+
+    pub fn input_layer(&self) -> &[I] {
+        &self.input_layer
+    }
+
+    /// Gets the neurons in a hidden (or output) layer. Note that layers are
+    /// numbered from 1 since the input layer is layer 0.
+    ///
+    /// Panics if the layer is the input layer or does not exist.
+    pub fn layer(&self, layer: usize) -> &[N] {
+        assert!(layer != INPUT_LAYER, "Use `input_layer()` for layer 0");
+        assert!(layer < self.num_layers(), "Layer {} does not exist", layer);
+        &self.layers[layer - 1]
+    }
+
+    /// Gets the synapses going from layer `from` to layer `to` as `(pre,
+    /// post, synapse)`. The synapses are brought up to date first.
+    pub fn connections(
+        &mut self,
+        from: usize,
+        to: usize,
+    ) -> impl Iterator<Item = (usize, usize, &S)> {
+        for proj in self.projections.iter_mut() {
+            if proj.from == from && proj.to == to {
+                proj.synapses.flush();
+            }
+        }
+        self.projections
+            .iter()
+            .filter(move |p| p.from == from && p.to == to)
+            .flat_map(|p| p.synapses.iter())
+    }
+
+    /// The time simulated so far.
+    pub fn time(&self) -> T {
+        self.time
+    }
+
+    /// The voltages of all the neurons in a layer.
+    pub fn voltages(&self, layer: usize) -> Vec<V> {
+        if layer == INPUT_LAYER {
+            layer_voltages(&self.input_layer)
+        } else {
+            layer_voltages(self.layer(layer))
+        }
+    }
+
+    /// Whether each neuron in a layer spiked in the last step.
+    pub fn spikes(&self, layer: usize) -> Vec<bool> {
+        if layer == INPUT_LAYER {
+            layer_spikes::<_, V>(&self.input_layer)
+        } else {
+            layer_spikes::<_, V>(self.layer(layer))
+        }
+    }
+
+    /// Advances the whole network by one time-step.
+    pub fn step(&mut self, dt: T) {
+        self.step_with_inputs(dt, &[]);
+    }
+
+    /// Advances the whole network by one time-step, adding each `(layer,
+    /// input)` pair to the input of every neuron in that layer (to the first
+    /// port of neurons with several).
+    ///
+    /// Panics if any of the layers is the input layer.
+    pub fn step_with_inputs(&mut self, dt: T, external: &[(usize, V)]) {
+        self.update_neurons(dt, external, update_layer, SynapseGroup::propagate);
+        self.step_synapses(dt);
+    }
+
+    /// Advances the neurons by one time-step, and hands their spikes to the
+    /// synapses, but does not advance the synapses themselves. Together with
+    /// `step_synapses`, this lets the synapses run on a slower clock than the
+    /// neurons, such as with a `Scheduler`. The time of the network is that of
+    /// its neurons.
+    pub fn step_neurons(&mut self, dt: T) {
+        self.update_neurons(dt, &[], update_layer, SynapseGroup::propagate);
+    }
+
+    /// Advances every synapse by one time-step of their own. See
+    /// `step_neurons`.
+    pub fn step_synapses(&mut self, dt: T) {
+        for proj in self.projections.iter_mut() {
+            proj.synapses.advance_synapses(dt);
+        }
+    }
+
+    /// Advances the neurons, updating each layer with `update` after the
+    /// synapses `propagate` the voltages into their inputs.
+    fn update_neurons(
+        &mut self,
+        dt: T,
+        external: &[(usize, V)],
+        update: fn(&mut [N], Vec<Vec<V>>, T),
+        propagate: Propagate<S, V, T>,
+    ) {
+        let voltages: Vec<Vec<V>> = (0..self.num_layers()).map(|l| self.voltages(l)).collect();
+        // The inputs of each layer, by port and then by neuron.
+        let mut inputs: Vec<Vec<Vec<V>>> = self.layers.iter().map(|l| zero_inputs(l)).collect();
+        for &(layer, input) in external.iter() {
+            assert!(
+                layer != INPUT_LAYER,
+                "The input layer cannot receive inputs"
+            );
+            for neuron_input in inputs[layer - 1][0].iter_mut() {
+                *neuron_input = *neuron_input + input;
+            }
+        }
+        for proj in self.projections.iter_mut() {
+            propagate(
+                &mut proj.synapses,
+                &voltages[proj.from],
+                &mut inputs[proj.to - 1][proj.port],
+            );
+        }
+
+        advance_input_layer(&mut self.input_layer, dt);
+        for (layer, layer_inputs) in self.layers.iter_mut().zip(inputs) {
+            update(layer, layer_inputs, dt);
+        }
+
+        // The synapses learn from the spikes of this step.
+        let voltages: Vec<Vec<V>> = (0..self.num_layers()).map(|l| self.voltages(l)).collect();
+        let spikes: Vec<Vec<bool>> = (0..self.num_layers()).map(|l| self.spikes(l)).collect();
+        for proj in self.projections.iter_mut() {
+            proj.synapses.learn(
+                &spikes[proj.from],
+                &voltages[proj.from],
+                &spikes[proj.to],
+                &voltages[proj.to],
+                dt,
+            );
+        }
+        self.time += dt;
+    }
+
+    /// Runs the network for `duration`, in steps of `dt`. The number of steps
+    /// is `duration / dt`, rounded to the nearest integer.
+    pub fn run(&mut self, duration: T, dt: T) {
+        for _ in 0..num_steps(duration, dt) {
+            self.step(dt);
+        }
+    }
+}
+
+/// The `par_` versions of the methods above, described in `parallel`.
+impl<I, N, S, V, T> Network<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T> + Send,
+    S: Synaptic<V, T> + Send,
+    V: Voltage + Send,
+    T: TimeStep + Send + Sync,
+{
+    /// As `step`, updating the layers in parallel.
+    pub fn par_step(&mut self, dt: T) {
+        self.par_step_neurons(dt);
+        self.step_synapses(dt);
+    }
+
+    /// As `step_neurons`, updating the layers in parallel.
+    pub fn par_step_neurons(&mut self, dt: T) {
+        self.update_neurons(dt, &[], par_update_layer, SynapseGroup::par_propagate);
+    }
+
+    /// As `run`, updating the layers in parallel.
+    pub fn par_run(&mut self, duration: T, dt: T) {
+        for _ in 0..num_steps(duration, dt) {
+            self.par_step(dt);
+        }
+    }
+}
+
+/// The inputs of a layer before any synapse adds to them: zero, by port and
+/// then by neuron.
+pub fn zero_inputs<N, V, T>(layer: &[N]) -> Vec<Vec<V>>
+where
+    N: InnerSpikeGenerator<V, T>,
+    V: Voltage,
+{
+    let ports = layer.first().map_or(1, |n| n.ports().len());
+    vec![vec![(0.0 * si::V).into(); layer.len()]; ports]
+}
+
+/// The index of the input port called `name` of the neurons of a layer, or
+/// `None` if they have no such port. Neurons with a single port call it
+/// `input`.
+pub fn port_index<N, V, T>(layer: &[N], name: &str) -> Option<usize>
+where
+    N: InnerSpikeGenerator<V, T>,
+{
+    layer
+        .first()
+        .map_or(&["input"][..], |n| n.ports())
+        .iter()
+        .position(|p| *p == name)
+}
+
+/// The voltages of the neurons of a layer.
+pub fn layer_voltages<N: SpikeGenerator<V>, V>(layer: &[N]) -> Vec<V> {
+    layer.iter().map(|n| n.get_voltage()).collect()
+}
+
+/// Whether each neuron of a layer spiked in the last step.
+pub fn layer_spikes<N: SpikeGenerator<V>, V>(layer: &[N]) -> Vec<bool> {
+    layer.iter().map(|n| n.did_spike()).collect()
+}
+
+/// Advances every neuron of an input layer by a time-step.
+pub fn advance_input_layer<I, V, T>(layer: &mut [I], dt: T)
+where
+    I: InputSpikeGenerator<V, T>,
+    T: Copy,
+{
+    for neuron in layer.iter_mut() {
+        neuron.advance(dt);
+    }
+}
+
+/// Hands every neuron of a layer its inputs, given by port and then by
+/// neuron, and advances it by a time-step.
+pub fn update_layer<N, V, T>(layer: &mut [N], mut inputs: Vec<Vec<V>>, dt: T)
+where
+    N: InnerSpikeGenerator<V, T>,
+    V: Copy,
+    T: Copy,
+{
+    if inputs.len() == 1 {
+        for (neuron, input) in layer.iter_mut().zip(inputs.pop().unwrap()) {
+            neuron.handle_input(input, dt);
+        }
+    } else {
+        for (i, neuron) in layer.iter_mut().enumerate() {
+            let by_port: Vec<V> = inputs.iter().map(|port| port[i]).collect();
+            neuron.handle_port_inputs(&by_port, dt);
+        }
+    }
+}
+
+/// As `update_layer`, spreading the neurons over threads if the `parallel`
+/// feature is on.
+pub fn par_update_layer<N, V, T>(layer: &mut [N], mut inputs: Vec<Vec<V>>, dt: T)
+where
+    N: InnerSpikeGenerator<V, T> + Send,
+    V: Copy + Send,
+    T: Copy + Send + Sync,
+{
+    if inputs.len() == 1 {
+        parallel::for_each_zipped(layer, inputs.pop().unwrap(), |neuron, input| {
+            neuron.handle_input(input, dt)
+        });
+    } else {
+        let by_neuron: Vec<Vec<V>> = (0..layer.len())
+            .map(|i| inputs.iter().map(|port| port[i]).collect())
+            .collect();
+        parallel::for_each_zipped(layer, by_neuron, |neuron, inputs| {
+            neuron.handle_port_inputs(&inputs, dt)
+        });
+    }
+}
+
+/// The time at which a network starts.
+pub fn start_time<T: TimeStep>() -> T {
+    (0.0 * si::S).into()
+}
+
+/// The number of steps of `dt` in `duration`, rounded to the nearest integer.
+pub fn num_steps<T: TimeStep>(duration: T, dt: T) -> usize {
+    let duration: si::Second<f64> = duration.into();
+    (*(duration / dt.into())).round() as usize
+}
+
+/// An input neuron that cannot be made, for networks that have no input layer
+/// of their own (such as most `MiddleSnn`s).
+pub enum NoInput {}
+
+impl<V> SpikeGenerator<V> for NoInput {
+    fn did_spike(&self) -> bool {
+        match *self {}
+    }
+
+    fn get_voltage(&self) -> V {
+        match *self {}
+    }
+}
+
+impl<V, T> InputSpikeGenerator<V, T> for NoInput {
+    fn advance(&mut self, _dt: T) {
+        match *self {}
+    }
+}
+
+/// Builds a `Network` layer by layer.
+///
+/// Layers are numbered in the order they are added, starting from 1 (the
+/// input layer is layer 0). Projections can only be added between layers that
+/// already exist.
+pub struct NetworkBuilder<I, N, S, V, T> {
+    input_layer: Vec<I>,
+    layers: Vec<Vec<N>>,
+    projections: Vec<Projection<S, V, T>>,
+    _voltage: PhantomData<V>,
+    _time: PhantomData<T>,
+}
+
+impl<I, N, S, V, T> NetworkBuilder<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    /// Starts a network with the given input layer.
+    pub fn new(input_layer: Vec<I>) -> Self {
+        NetworkBuilder {
+            input_layer,
+            layers: Vec::new(),
+            projections: Vec::new(),
+            _voltage: PhantomData,
+            _time: PhantomData,
+        }
+    }
+
+    /// Adds a layer of `size` neurons, where the i-th neuron is
+    /// `make_neuron(i)`.
+    pub fn layer<F: FnMut(usize) -> N>(mut self, size: usize, make_neuron: F) -> Self {
+        self.layers.push((0..size).map(make_neuron).collect());
+        self
+    }
+
+    /// Connects layer `from` to layer `to` through the given synapses.
+    ///
+    /// Panics if either layer does not exist yet, if `to` is the input layer,
+    /// or if a connection refers to a neuron outside of its layer.
+    pub fn connect(self, from: usize, to: usize, connections: Vec<Connection<S>>) -> Self {
+        let group = SynapseGroup::new(self.layer_size(from), self.layer_size(to), connections);
+        self.connect_group(from, to, group)
+    }
+
+    /// Connects layer `from` to layer `to` according to a connection rule,
+    /// making the synapse from neuron `i` to neuron `j` with
+    /// `make_synapse(i, j)`. The seed is used by the random rules.
+    ///
+    /// Panics if either layer does not exist yet, if `to` is the input layer,
+    /// or if the rule cannot be met by the layers.
+    pub fn connect_with<F: FnMut(usize, usize) -> S>(
+        self,
+        from: usize,
+        to: usize,
+        rule: &Rule,
+        seed: u64,
+        make_synapse: F,
+    ) -> Self {
+        let group = connect::connect(
+            self.layer_size(from),
+            self.layer_size(to),
+            rule,
+            seed,
+            make_synapse,
+        );
+        self.connect_group(from, to, group)
+    }
+
+    /// Connects layer `from` to layer `to` through a group of synapses.
+    ///
+    /// Panics if either layer does not exist yet, if `to` is the input layer,
+    /// or if the group does not match the sizes of the layers.
+    pub fn connect_group(
+        mut self,
+        from: usize,
+        to: usize,
+        synapses: SynapseGroup<S, V, T>,
+    ) -> Self {
+        assert!(to != INPUT_LAYER, "The input layer cannot receive synapses");
+        assert!(
+            synapses.num_pre() == self.layer_size(from)
+                && synapses.num_post() == self.layer_size(to),
+            "The synapses do not match the sizes of layers {} -> {}",
+            from,
+            to
+        );
+        self.projections.push(Projection {
+            from,
+            to,
+            port: 0,
+            synapses,
+        });
+        self
+    }
+
+    /// Routes the synapses connected last into the input port called `name`
+    /// of the neurons of their target layer, instead of the first port.
+    ///
+    /// Panics if nothing was connected yet or if the neurons of the target
+    /// layer have no such port.
+    pub fn to_port(mut self, name: &str) -> Self {
+        let proj = self
+            .projections
+            .last_mut()
+            .expect("There are no synapses to route to a port");
+        proj.port = port_index(&self.layers[proj.to - 1], name)
+            .unwrap_or_else(|| panic!("Layer {} has no port `{}`", proj.to, name));
+        self
+    }
+
+    /// The number of neurons in a layer that was already added.
+    ///
+    /// Panics if the layer does not exist yet.
+    fn layer_size(&self, layer: usize) -> usize {
+        assert!(layer <= self.layers.len(), "Layer {} does not exist", layer);
+        if layer == INPUT_LAYER {
+            self.input_layer.len()
+        } else {
+            self.layers[layer - 1].len()
+        }
+    }
+
+    pub fn build(self) -> Network<I, N, S, V, T> {
+        Network {
+            input_layer: self.input_layer,
+            layers: self.layers,
+            projections: self.projections,
+            time: start_time(),
+            _voltage: PhantomData,
+        }
+    }
+}
+
+/// An "Input SNN": a network that is itself an input spike generator, so it can
+/// be used anywhere a `SpikeAtTimes` or `WithSpikeDecay` could be.
+///
+/// One layer of the network is designated as the output. The SNN spikes
+/// whenever any neuron in the output layer spikes and its voltage is the sum
+/// of the voltages of the output layer.
+pub struct InputSnn<I, N, S, V, T> {
+    network: Network<I, N, S, V, T>,
+    output_layer: usize,
+}
+
+impl<I, N, S, V, T> InputSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    /// Panics if the output layer is not in the network.
+    pub fn new(network: Network<I, N, S, V, T>, output_layer: usize) -> Self {
+        assert!(
+            output_layer < network.num_layers(),
+            "Layer {} does not exist",
+            output_layer
+        );
+        InputSnn {
+            network,
+            output_layer,
+        }
+    }
+
+    pub fn network(&self) -> &Network<I, N, S, V, T> {
+        &self.network
+    }
+
+    pub fn output_layer(&self) -> usize {
+        self.output_layer
+    }
+}
+
+impl<I, N, S, V, T> SpikeGenerator<V> for InputSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    fn did_spike(&self) -> bool {
+        self.network
+            .spikes(self.output_layer)
+            .into_iter()
+            .any(|s| s)
+    }
+
+    fn get_voltage(&self) -> V {
+        self.network
+            .voltages(self.output_layer)
+            .into_iter()
+            .fold((0.0 * si::V).into(), |acc, v| acc + v)
+    }
+}
+
+impl<I, N, S, V, T> InputSpikeGenerator<V, T> for InputSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    fn advance(&mut self, dt: T) {
+        self.network.step(dt);
+    }
+}
+
+/// A "Middle SNN": a network that is itself a hidden neuron, so that networks
+/// can be composed into deeper hierarchies.
+///
+/// The input handed to the SNN is added to the input of every neuron in its
+/// designated input layer. As with an `InputSnn`, the SNN spikes whenever any
+/// neuron in the output layer spikes and its voltage is the sum of the
+/// voltages of the output layer.
+pub struct MiddleSnn<I, N, S, V, T> {
+    network: Network<I, N, S, V, T>,
+    input_layer: usize,
+    output_layer: usize,
+}
+
+impl<I, N, S, V, T> MiddleSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    /// Panics if either layer is not in the network or if the input layer is
+    /// the network's own input layer.
+    pub fn new(network: Network<I, N, S, V, T>, input_layer: usize, output_layer: usize) -> Self {
+        assert!(
+            input_layer != INPUT_LAYER,
+            "The input layer cannot receive inputs"
+        );
+        assert!(
+            input_layer < network.num_layers(),
+            "Layer {} does not exist",
+            input_layer
+        );
+        assert!(
+            output_layer < network.num_layers(),
+            "Layer {} does not exist",
+            output_layer
+        );
+        MiddleSnn {
+            network,
+            input_layer,
+            output_layer,
+        }
+    }
+
+    pub fn network(&self) -> &Network<I, N, S, V, T> {
+        &self.network
+    }
+
+    pub fn input_layer(&self) -> usize {
+        self.input_layer
+    }
+
+    pub fn output_layer(&self) -> usize {
+        self.output_layer
+    }
+}
+
+impl<I, N, S, V, T> SpikeGenerator<V> for MiddleSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    fn did_spike(&self) -> bool {
+        self.network
+            .spikes(self.output_layer)
+            .into_iter()
+            .any(|s| s)
+    }
+
+    fn get_voltage(&self) -> V {
+        self.network
+            .voltages(self.output_layer)
+            .into_iter()
+            .fold((0.0 * si::V).into(), |acc, v| acc + v)
+    }
+}
+
+impl<I, N, S, V, T> InnerSpikeGenerator<V, T> for MiddleSnn<I, N, S, V, T>
+where
+    I: InputSpikeGenerator<V, T>,
+    N: InnerSpikeGenerator<V, T>,
+    S: Synaptic<V, T>,
+    V: Voltage,
+    T: TimeStep,
+{
+    fn handle_input(&mut self, input: V, dt: T) {
+        self.network
+            .step_with_inputs(dt, &[(self.input_layer, input)]);
+    }
+}
@@ -0,0 +1,53 @@
+//! White noise for stochastic differential equations.
+//!
+//! Neurons made by `define_neuron!` with a noise term `xi` in their equations
+//! hold a `Noise` and draw one sample of `xi` from it per step. As with the
+//! other randomness in the crate, it takes a seed so that the same seed always
+//! gives the same run, and draws from ChaCha8 so that it stays the same across
+//! versions of rand.
+
+extern crate dimensioned as dim;
+extern crate rand;
+extern crate rand_chacha;
+
+use dim::si;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// A seedable source of standard normal samples.
+#[derive(Clone, Debug)]
+pub struct Noise {
+    rng: ChaCha8Rng,
+    // The Box-Muller transform makes samples in pairs.
+    spare: Option<f64>,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        Noise {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            spare: None,
+        }
+    }
+
+    /// A sample of the standard normal distribution.
+    pub fn standard_normal(&mut self) -> f64 {
+        if let Some(spare) = self.spare.take() {
+            return spare;
+        }
+        // `gen` is in [0, 1), so the radius is finite.
+        let radius = (-2.0 * (1.0 - self.rng.gen::<f64>()).ln()).sqrt();
+        let angle = 2.0 * std::f64::consts::PI * self.rng.gen::<f64>();
+        self.spare = Some(radius * angle.sin());
+        radius * angle.cos()
+    }
+
+    /// A sample of `xi` over a step of `dt`: a standard normal divided by
+    /// the square root of `dt` in seconds. Multiplied by `dt`, as in an Euler
+    /// step, it is the increment of a Wiener process, which makes the step
+    /// Euler-Maruyama.
+    pub fn xi<T: Into<si::Second<f64>>>(&mut self, dt: T) -> f64 {
+        let seconds = *(dt.into() / si::S);
+        self.standard_normal() / seconds.sqrt()
+    }
+}
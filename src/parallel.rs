@@ -0,0 +1,64 @@
+//! Support for the optional `parallel` feature, which spreads the updates of
+//! a population over threads with rayon.
+//!
+//! The parallel paths are separate methods, named with a `par_` prefix (such
+//! as `Network::par_step`), which need thread-safe neurons and synapses. They
+//! exist with or without the feature, which only decides whether they use
+//! threads, so turning it on never changes what compiles.
+//!
+//! Only work that is independent from neuron to neuron (or synapse to
+//! synapse) is spread out, and sums are still done in order, so the results
+//! are bit-for-bit the same as those of the sequential methods.
+
+/// Calls `f` on every item of `items`, spread over threads if the `parallel`
+/// feature is on.
+#[cfg(feature = "parallel")]
+pub(crate) fn for_each<A, F>(items: &mut [A], f: F)
+where
+    A: Send,
+    F: Fn(&mut A) + Send + Sync,
+{
+    use rayon::prelude::*;
+    items.par_iter_mut().for_each(f);
+}
+
+/// Calls `f` on every item of `items`, spread over threads if the `parallel`
+/// feature is on.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn for_each<A, F>(items: &mut [A], f: F)
+where
+    A: Send,
+    F: Fn(&mut A) + Send + Sync,
+{
+    items.iter_mut().for_each(f);
+}
+
+/// Calls `f` on every item of `items` with the matching item of `other`,
+/// spread over threads if the `parallel` feature is on.
+#[cfg(feature = "parallel")]
+pub(crate) fn for_each_zipped<A, B, F>(items: &mut [A], other: Vec<B>, f: F)
+where
+    A: Send,
+    B: Send,
+    F: Fn(&mut A, B) + Send + Sync,
+{
+    use rayon::prelude::*;
+    items
+        .par_iter_mut()
+        .zip(other.into_par_iter())
+        .for_each(|(a, b)| f(a, b));
+}
+
+/// Calls `f` on every item of `items` with the matching item of `other`,
+/// spread over threads if the `parallel` feature is on.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn for_each_zipped<A, B, F>(items: &mut [A], other: Vec<B>, f: F)
+where
+    A: Send,
+    B: Send,
+    F: Fn(&mut A, B) + Send + Sync,
+{
+    for (a, b) in items.iter_mut().zip(other) {
+        f(a, b);
+    }
+}
@@ -0,0 +1,126 @@
+//! Access to the variables of a model by name, for tools such as monitors,
+//! checkpoints and parameter sweeps that should work with any model.
+//!
+//! `define_neuron!` and `define_synapse!` implement `Reflect` for the types
+//! marked `#[reflect]`, whose variables must all be `Magnitude`. Values
+//! are read and written as `f64` magnitudes in SI base units, so a tool
+//! does not need to know the types of the variables.
+
+extern crate dimensioned as dim;
+
+use dim::si;
+use dim::typenum::{ATerm, Integer, TArr};
+
+/// A value that can be read and written as an `f64` magnitude.
+pub trait Magnitude {
+    /// The units of the magnitude, in SI base units: `m^2*kg*s^-3*A^-1` for
+    /// a voltage, say, or the empty string for a plain number.
+    fn units() -> String;
+
+    fn to_magnitude(&self) -> f64;
+
+    fn from_magnitude(magnitude: f64) -> Self;
+}
+
+impl Magnitude for f64 {
+    fn units() -> String {
+        String::new()
+    }
+
+    fn to_magnitude(&self) -> f64 {
+        *self
+    }
+
+    fn from_magnitude(magnitude: f64) -> Self {
+        magnitude
+    }
+}
+
+impl Magnitude for f32 {
+    fn units() -> String {
+        String::new()
+    }
+
+    fn to_magnitude(&self) -> f64 {
+        *self as f64
+    }
+
+    fn from_magnitude(magnitude: f64) -> Self {
+        magnitude as f32
+    }
+}
+
+/// The SI base units, in the order of their exponents in the type of a
+/// quantity.
+const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "cd", "mol"];
+
+/// The exponents of the base units of a quantity, as `dimensioned` keeps
+/// them in its type: a type-level array of integers.
+pub trait Exponents {
+    fn exponents() -> Vec<i32>;
+}
+
+impl Exponents for ATerm {
+    fn exponents() -> Vec<i32> {
+        Vec::new()
+    }
+}
+
+impl<I: Integer, Rest: Exponents> Exponents for TArr<I, Rest> {
+    fn exponents() -> Vec<i32> {
+        let mut exponents = vec![I::to_i32()];
+        exponents.extend(Rest::exponents());
+        exponents
+    }
+}
+
+impl<V: Magnitude, U: Exponents> Magnitude for si::SI<V, U> {
+    fn units() -> String {
+        let units: Vec<String> = BASE_UNITS
+            .iter()
+            .zip(U::exponents())
+            .filter(|(_, exponent)| *exponent != 0)
+            .map(|(unit, exponent)| match exponent {
+                1 => unit.to_string(),
+                _ => format!("{}^{}", unit, exponent),
+            })
+            .collect();
+        units.join("*")
+    }
+
+    fn to_magnitude(&self) -> f64 {
+        self.value_unsafe.to_magnitude()
+    }
+
+    fn from_magnitude(magnitude: f64) -> Self {
+        si::SI::new(V::from_magnitude(magnitude))
+    }
+}
+
+/// The parameters and state variables of a model, by name.
+///
+/// The names are those declared in `params` and `initialize`. The fields
+/// that the macros add themselves, such as the refractory countdown, are
+/// not listed.
+pub trait Reflect {
+    /// The names of the parameters, in the order they are declared.
+    fn params(&self) -> &'static [&'static str];
+
+    /// The names of the state variables, in the order they are declared.
+    fn state_vars(&self) -> &'static [&'static str];
+
+    /// The units of a variable, as in `Magnitude::units`.
+    ///
+    /// Panics if there is no such variable.
+    fn units(&self, name: &str) -> String;
+
+    /// The magnitude of a variable.
+    ///
+    /// Panics if there is no such variable.
+    fn get_var(&self, name: &str) -> f64;
+
+    /// Sets a variable from its magnitude.
+    ///
+    /// Panics if there is no such variable.
+    fn set_var(&mut self, name: &str, magnitude: f64);
+}
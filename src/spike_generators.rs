@@ -13,8 +13,26 @@ pub trait SpikeGenerator<V> {
 
 /// An extension of a neuron that is in a hidden layer. Such a neuron will have
 /// a voltage as well as a time-step as input.
+///
+/// A neuron may also have several named input ports, such as excitatory and
+/// inhibitory inputs, so that they need not be summed before they reach it.
+/// `handle_input` then feeds the first port.
 pub trait InnerSpikeGenerator<V, T>: SpikeGenerator<V> {
     fn handle_input(&mut self, input: V, dt: T);
+
+    /// The names of the input ports, in order. Most neurons have one.
+    fn ports(&self) -> &'static [&'static str] {
+        &["input"]
+    }
+
+    /// Handles one input per port, in the order of `ports`.
+    fn handle_port_inputs(&mut self, inputs: &[V], dt: T)
+    where
+        V: Copy,
+    {
+        assert_eq!(inputs.len(), 1, "Expected one input per port");
+        self.handle_input(inputs[0], dt);
+    }
 }
 
 /// An extension of a neuron for input neurons. These neurons can be advanced
@@ -59,11 +77,11 @@ pub mod discrete {
 	/// absolute error.
         pub fn new(times: Vec<T>, tolerance: T, spike_voltage: I) -> SpikeAtTimes<T, I> {
             SpikeAtTimes {
-                times: times,
+                times,
                 time: (0.0 * si::S).into(),
                 error_tolerance: tolerance,
                 idx: 0,
-                spike_voltage: spike_voltage,
+                spike_voltage,
             }
         }
     }
@@ -86,12 +104,12 @@ pub mod discrete {
                 self.idx
             };
             let time_diff = self.times[idx] - self.time;
-            return -self.error_tolerance < time_diff && time_diff < self.error_tolerance;
+            -self.error_tolerance < time_diff && time_diff < self.error_tolerance
         }
 
         fn get_voltage(&self) -> V {
             if self.did_spike() {
-                self.spike_voltage.into()
+                self.spike_voltage
             } else {
                 (0.0 * si::V).into()
             }
@@ -110,7 +128,7 @@ pub mod discrete {
         V: From<si::Volt<f64>> + Copy,
     {
         fn advance(&mut self, dt: T) {
-            self.time += dt.into();
+            self.time += dt;
             while self.idx < self.times.len() && self.times[self.idx] < self.time {
                 self.idx += 1;
             }
@@ -141,17 +159,17 @@ pub mod discrete {
 	///
 	/// Args:
 	/// * `rate_fn`: Returns the rate at which the neuron should spike at at a given
-	///              time. It also returns a deadline for when all those spikes
-	///              should occur. If the function returns None, it is assumed that
-	///              the neuron is done spiking.
+	///   time. It also returns a deadline for when all those spikes
+	///   should occur. If the function returns None, it is assumed that
+	///   the neuron is done spiking.
 	/// * `slot_end_time`: When the first starting_rate spikes should occur by.
 	/// * `spike_voltage`: The voltage to spike at when spiking.
 	/// * `starting_rate`: The initial rate to spike at.
 	/// * `tolerance`: "tolerance" is an implementation detail, but an important one: since
-	///                slots are subdivided to ensure the correct number of spikes in the slot
-	///                the tolerance is "how far from the starting of a sub-slot should the
-	///                spike be within." Hence, for a tolerance t, you want to advance in a
-	///                step t < dt < 2t to be sure that you hit every spike exactly once.
+	///   slots are subdivided to ensure the correct number of spikes in the slot
+	///   the tolerance is "how far from the starting of a sub-slot should the
+	///   spike be within." Hence, for a tolerance t, you want to advance in a
+	///   step t < dt < 2t to be sure that you hit every spike exactly once.
         pub fn new(
             rate_fn: Box<dyn Fn(T) -> Option<(i32, T)>>,
             slot_end_time: T,
@@ -163,11 +181,11 @@ pub mod discrete {
                 rate_at_time: rate_fn,
                 time: (0.0 * si::S).into(),
                 slot_start_time: (0.0 * si::S).into(),
-                slot_end_time: slot_end_time,
-                spike_voltage: spike_voltage,
+                slot_end_time,
+                spike_voltage,
                 current_rate: starting_rate,
                 num_spiked: 0,
-                tolerance: tolerance,
+                tolerance,
             }
         }
 
@@ -195,11 +213,11 @@ pub mod discrete {
                     .filter(|slt| time > slt.0)
                     .take(1)
                     .collect();
-                if slot.len() == 0 {
+                if slot.is_empty() {
                     return Option::None;
                 }
                 let (new_slot_end, new_rate) = slot[0];
-                return Option::Some((*new_rate, *new_slot_end));
+                Option::Some((*new_rate, *new_slot_end))
             })
         }
     }
@@ -291,16 +309,16 @@ pub mod continuous {
 	/// Args:
 	/// * `discrete_neuron`: The discrete neuron to add a decay to.
 	/// * `spike_decay_fn`: The function to decay along. The first argument is the time of
-	///                     the previous spike and the second is the voltage at the spike.
+	///   the previous spike and the second is the voltage at the spike.
         pub fn new(
 	    discrete_neuron: D,
 	    spike_decay_fn: Box<dyn Fn(T, V) -> V>) -> Self {
             WithSpikeDecay {
                 time_since_spike: (0.0 * si::S).into(),
-                discrete_neuron: discrete_neuron,
+                discrete_neuron,
                 spike_voltage: (0.0 * si::V).into(),
                 spiked_yet: false,
-                spike_decay_fn: spike_decay_fn,
+                spike_decay_fn,
             }
         }
 
@@ -1,12 +1,518 @@
+//! This module represents synapses and the groups they are stored in.
+
 extern crate dimensioned as dim;
 
-use dim::dimensions;
+use dim::si;
 
-pub trait Synaptic<V, T>{
+use crate::parallel;
 
+pub trait Synaptic<V, T> {
     fn on_pre(&mut self, input: V);
     fn on_post(&mut self, input: V);
     fn current_weight(&self) -> f64;
 
     fn advance_once(&mut self, dt: T);
 }
+
+/// A synapse from the `pre`-th neuron of a population to the `post`-th neuron
+/// of another.
+pub struct Connection<S> {
+    pub pre: usize,
+    pub post: usize,
+    pub synapse: S,
+}
+
+impl<S> Connection<S> {
+    pub fn new(pre: usize, post: usize, synapse: S) -> Self {
+        Connection { pre, post, synapse }
+    }
+}
+
+/// All the synapses from one population of neurons to another, stored in
+/// compressed sparse row (CSR) form.
+///
+/// The synapses are sorted by presynaptic neuron, so the synapses of the i-th
+/// presynaptic neuron are `synapses[row_starts[i]..row_starts[i + 1]]`. A
+/// second index lists the synapses onto each postsynaptic neuron. This means
+/// that memory only grows with the number of synapses (not the number of
+/// pairs of neurons) and that a spike only touches the synapses of the neuron
+/// that spiked.
+///
+/// Synapses are also advanced lazily: `advance` only counts the steps and a
+/// synapse catches up (by calling `advance_once` for every step it missed)
+/// right before it is next read or handed an event. This gives the same
+/// results as advancing every synapse on every step. Use `flush` to catch up
+/// every synapse before reading them with `iter`. Note that `propagate` reads
+/// the synapses of every neuron with a voltage, so the savings depend on how
+/// few neurons carry one.
+///
+/// Synapses can have transmission delays, rounded to a whole number of steps.
+/// Both the `on_pre` event of a spike and the weighted voltage sent through a
+/// synapse are held in a ring buffer and arrive exactly that many steps later.
+/// The weight is read when the voltage is sent rather than when it arrives.
+/// The delays are counted in steps of the time-step they were set up with, so
+/// the group must then be advanced by that time-step only.
+///
+/// `advance` moves both the synapses and the delays on by a step. To update
+/// the synapses on a slower clock than the neurons, as is common for
+/// plasticity, call `tick` on every step of the neurons (so that spikes are
+/// still handled and delayed at their resolution) and `advance_synapses` on
+/// every step of the synapses instead.
+pub struct SynapseGroup<S, V, T> {
+    num_pre: usize,
+    num_post: usize,
+    row_starts: Vec<usize>,
+    posts: Vec<usize>,
+    synapses: Vec<S>,
+    column_starts: Vec<usize>,
+    column_synapses: Vec<usize>,
+    steps: u64,
+    ticks: u64,
+    last_update: Vec<u64>,
+    dt: Option<T>,
+    delays: Delays,
+    delay_dt: Option<T>,
+    pending_pre: Vec<Vec<(usize, V)>>,
+    pending_inputs: Vec<Vec<V>>,
+}
+
+/// Transmission delays, in steps.
+enum Delays {
+    Homogeneous(usize),
+    PerSynapse(Vec<usize>),
+}
+
+impl Delays {
+    fn of(&self, idx: usize) -> usize {
+        match self {
+            Delays::Homogeneous(delay) => *delay,
+            Delays::PerSynapse(delays) => delays[idx],
+        }
+    }
+
+    fn max(&self) -> usize {
+        match self {
+            Delays::Homogeneous(delay) => *delay,
+            Delays::PerSynapse(delays) => delays.iter().cloned().max().unwrap_or(0),
+        }
+    }
+}
+
+/// Rounds a delay to a whole number of time-steps.
+fn delay_in_steps<T: Into<si::Second<f64>>>(delay: T, dt: T) -> usize {
+    let delay: si::Second<f64> = delay.into();
+    let steps = (*(delay / dt.into())).round();
+    assert!(steps >= 0.0, "Delays cannot be negative");
+    steps as usize
+}
+
+impl<S, V, T> SynapseGroup<S, V, T> {
+    /// Makes a group of synapses from `num_pre` to `num_post` neurons.
+    ///
+    /// Panics if a connection refers to a neuron outside of its population.
+    pub fn new(num_pre: usize, num_post: usize, connections: Vec<Connection<S>>) -> Self {
+        for conn in connections.iter() {
+            assert!(
+                conn.pre < num_pre && conn.post < num_post,
+                "Connection {} -> {} is out of bounds for {} -> {} neurons",
+                conn.pre,
+                conn.post,
+                num_pre,
+                num_post
+            );
+        }
+        let mut connections = connections;
+        // A stable sort keeps the synapses of a neuron in the order given.
+        connections.sort_by_key(|conn| conn.pre);
+
+        let mut row_starts = vec![0; num_pre + 1];
+        let mut column_starts = vec![0; num_post + 1];
+        for conn in connections.iter() {
+            row_starts[conn.pre + 1] += 1;
+            column_starts[conn.post + 1] += 1;
+        }
+        for i in 0..num_pre {
+            row_starts[i + 1] += row_starts[i];
+        }
+        for i in 0..num_post {
+            column_starts[i + 1] += column_starts[i];
+        }
+
+        let mut column_synapses = vec![0; connections.len()];
+        let mut column_fill = column_starts.clone();
+        let mut posts = Vec::with_capacity(connections.len());
+        let mut synapses = Vec::with_capacity(connections.len());
+        for (idx, conn) in connections.into_iter().enumerate() {
+            column_synapses[column_fill[conn.post]] = idx;
+            column_fill[conn.post] += 1;
+            posts.push(conn.post);
+            synapses.push(conn.synapse);
+        }
+        let last_update = vec![0; synapses.len()];
+        SynapseGroup {
+            num_pre,
+            num_post,
+            row_starts,
+            posts,
+            synapses,
+            column_starts,
+            column_synapses,
+            steps: 0,
+            ticks: 0,
+            last_update,
+            dt: None,
+            delays: Delays::Homogeneous(0),
+            delay_dt: None,
+            pending_pre: Vec::new(),
+            pending_inputs: Vec::new(),
+        }
+    }
+
+    pub fn num_pre(&self) -> usize {
+        self.num_pre
+    }
+
+    pub fn num_post(&self) -> usize {
+        self.num_post
+    }
+
+    /// The number of synapses in the group.
+    pub fn len(&self) -> usize {
+        self.synapses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.synapses.is_empty()
+    }
+
+    /// Iterates over the synapses as `(pre, post, synapse)`, sorted by `pre`.
+    ///
+    /// Synapses that have not been touched by a spike may be behind by a few
+    /// steps unless the group was flushed first.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, &S)> {
+        (0..self.num_pre).flat_map(move |pre| {
+            (self.row_starts[pre]..self.row_starts[pre + 1])
+                .map(move |idx| (pre, self.posts[idx], &self.synapses[idx]))
+        })
+    }
+
+    /// An estimate of the memory used by the group, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.synapses.capacity() * std::mem::size_of::<S>()
+            + (self.row_starts.capacity()
+                + self.posts.capacity()
+                + self.column_starts.capacity()
+                + self.column_synapses.capacity())
+                * std::mem::size_of::<usize>()
+            + self.last_update.capacity() * std::mem::size_of::<u64>()
+            + match &self.delays {
+                Delays::Homogeneous(_) => 0,
+                Delays::PerSynapse(delays) => delays.capacity() * std::mem::size_of::<usize>(),
+            }
+            + self
+                .pending_pre
+                .iter()
+                .map(|slot| slot.capacity() * std::mem::size_of::<(usize, V)>())
+                .sum::<usize>()
+            + self.pending_inputs.len() * self.num_post * std::mem::size_of::<V>()
+    }
+}
+
+/// Advances a synapse by the steps it missed since `last_update`.
+fn catch_up_synapse<S, V, T>(synapse: &mut S, last_update: &mut u64, steps: u64, dt: Option<T>)
+where
+    S: Synaptic<V, T>,
+    T: Copy,
+{
+    if let Some(dt) = dt {
+        for _ in *last_update..steps {
+            synapse.advance_once(dt);
+        }
+    }
+    *last_update = steps;
+}
+
+impl<S, V, T> SynapseGroup<S, V, T>
+where
+    S: Synaptic<V, T>,
+    V: From<si::Volt<f64>>
+        + Copy
+        + PartialEq
+        + std::ops::Add<Output = V>
+        + std::ops::Mul<f64, Output = V>,
+    T: Copy + PartialEq,
+{
+    fn catch_up(&mut self, idx: usize) {
+        catch_up_synapse(
+            &mut self.synapses[idx],
+            &mut self.last_update[idx],
+            self.steps,
+            self.dt,
+        );
+    }
+
+    /// Catches up the synapses of the presynaptic neurons for which `touched`
+    /// is true.
+    fn catch_up_rows<F: Fn(usize) -> bool>(&mut self, touched: F) {
+        let (steps, dt) = (self.steps, self.dt);
+        for (row_synapses, row_updates) in self.rows_mut(touched) {
+            for (synapse, last) in row_synapses.iter_mut().zip(row_updates.iter_mut()) {
+                catch_up_synapse(synapse, last, steps, dt);
+            }
+        }
+    }
+
+    /// The synapses and last updates of the presynaptic neurons for which
+    /// `touched` is true, row by row.
+    #[allow(clippy::type_complexity)]
+    fn rows_mut<F: Fn(usize) -> bool>(&mut self, touched: F) -> Vec<(&mut [S], &mut [u64])> {
+        let mut rows = Vec::new();
+        let mut synapses = &mut self.synapses[..];
+        let mut last_update = &mut self.last_update[..];
+        for pre in 0..self.num_pre {
+            let len = self.row_starts[pre + 1] - self.row_starts[pre];
+            let (row_synapses, rest_synapses) = synapses.split_at_mut(len);
+            let (row_updates, rest_updates) = last_update.split_at_mut(len);
+            synapses = rest_synapses;
+            last_update = rest_updates;
+            if touched(pre) {
+                rows.push((row_synapses, row_updates));
+            }
+        }
+        rows
+    }
+
+    /// Sets the ring buffers up for the longest delay. Spikes already in
+    /// transit are dropped.
+    fn reset_pending(&mut self) {
+        let zero: V = (0.0 * si::V).into();
+        let slots = match self.delays.max() {
+            0 => 0,
+            max => max + 1,
+        };
+        self.pending_pre = (0..slots).map(|_| Vec::new()).collect();
+        self.pending_inputs = (0..slots).map(|_| vec![zero; self.num_post]).collect();
+    }
+
+    /// Delays every synapse by the same time, rounded to a whole number of
+    /// steps of `dt`. This should be done before the group is first used,
+    /// since any spikes in transit are dropped, and the group must then only
+    /// be advanced by `dt`.
+    pub fn set_delay(&mut self, delay: T, dt: T)
+    where
+        T: Into<si::Second<f64>>,
+    {
+        self.delays = Delays::Homogeneous(delay_in_steps(delay, dt));
+        self.delay_dt = Some(dt);
+        self.reset_pending();
+    }
+
+    /// Delays the synapse from the i-th to the j-th neuron by `delay_of(i, j)`,
+    /// rounded to a whole number of steps of `dt`. This should be done before
+    /// the group is first used, since any spikes in transit are dropped, and
+    /// the group must then only be advanced by `dt`.
+    pub fn set_delays<F: FnMut(usize, usize) -> T>(&mut self, mut delay_of: F, dt: T)
+    where
+        T: Into<si::Second<f64>>,
+    {
+        let mut delays = Vec::with_capacity(self.synapses.len());
+        for pre in 0..self.num_pre {
+            for idx in self.row_starts[pre]..self.row_starts[pre + 1] {
+                delays.push(delay_in_steps(delay_of(pre, self.posts[idx]), dt));
+            }
+        }
+        self.delays = Delays::PerSynapse(delays);
+        self.delay_dt = Some(dt);
+        self.reset_pending();
+    }
+
+    /// The delay of every synapse, in steps, listed as in `iter`.
+    pub fn delays_in_steps(&self) -> Vec<usize> {
+        (0..self.synapses.len())
+            .map(|idx| self.delays.of(idx))
+            .collect()
+    }
+
+    /// Brings every synapse up to date.
+    pub fn flush(&mut self) {
+        let (steps, dt) = (self.steps, self.dt);
+        for (synapse, last) in self.synapses.iter_mut().zip(self.last_update.iter_mut()) {
+            catch_up_synapse(synapse, last, steps, dt);
+        }
+    }
+
+    /// Adds the voltage of every presynaptic neuron, weighted by each of its
+    /// synapses, to the inputs of the postsynaptic neurons. Neurons with no
+    /// voltage are skipped, so their synapses are not touched.
+    ///
+    /// The weights must be up to date, so every synapse of a neuron with a
+    /// voltage is caught up first. Neurons whose voltage is only non-zero as
+    /// they spike keep this cheap, but with continuous voltages (as from
+    /// `WithSpikeDecay`) every synapse is caught up on every step, which costs
+    /// as much as advancing them all eagerly.
+    ///
+    /// Delayed voltages are held back and added to the inputs of a later
+    /// step, so this must be called once per step when there are delays.
+    pub fn propagate(&mut self, pre_voltages: &[V], post_inputs: &mut [V]) {
+        let zero: V = (0.0 * si::V).into();
+        self.catch_up_rows(|pre| pre_voltages[pre] != zero);
+        self.send(pre_voltages, post_inputs);
+    }
+
+    /// Sends the voltages through synapses that are already caught up.
+    fn send(&mut self, pre_voltages: &[V], post_inputs: &mut [V]) {
+        let zero: V = (0.0 * si::V).into();
+        let slots = self.pending_inputs.len();
+        for (pre, &voltage) in pre_voltages.iter().enumerate() {
+            if voltage == zero {
+                continue;
+            }
+            for idx in self.row_starts[pre]..self.row_starts[pre + 1] {
+                self.catch_up(idx);
+                let post = self.posts[idx];
+                let weighted = voltage * self.synapses[idx].current_weight();
+                match self.delays.of(idx) {
+                    0 => post_inputs[post] = post_inputs[post] + weighted,
+                    delay => {
+                        let slot = &mut self.pending_inputs[(self.ticks as usize + delay) % slots];
+                        slot[post] = slot[post] + weighted;
+                    }
+                }
+            }
+        }
+        if slots > 0 {
+            let arrived = &mut self.pending_inputs[self.ticks as usize % slots];
+            for (input, pending) in post_inputs.iter_mut().zip(arrived.iter_mut()) {
+                *input = *input + *pending;
+                *pending = zero;
+            }
+        }
+    }
+
+    /// Calls `on_pre` on the synapses of every presynaptic neuron that
+    /// spiked, as well as on the synapses whose delayed spikes arrive now.
+    pub fn on_pre_spikes(&mut self, pre_spikes: &[bool], pre_voltages: &[V]) {
+        let slots = self.pending_pre.len();
+        if slots > 0 {
+            let slot = self.ticks as usize % slots;
+            let mut arrived = std::mem::take(&mut self.pending_pre[slot]);
+            for (idx, voltage) in arrived.drain(..) {
+                self.catch_up(idx);
+                self.synapses[idx].on_pre(voltage);
+            }
+            self.pending_pre[slot] = arrived;
+        }
+        for (pre, _) in pre_spikes.iter().enumerate().filter(|(_, &s)| s) {
+            for idx in self.row_starts[pre]..self.row_starts[pre + 1] {
+                match self.delays.of(idx) {
+                    0 => {
+                        self.catch_up(idx);
+                        self.synapses[idx].on_pre(pre_voltages[pre]);
+                    }
+                    delay => {
+                        let slot = (self.ticks as usize + delay) % slots;
+                        self.pending_pre[slot].push((idx, pre_voltages[pre]));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls `on_post` on the synapses onto every postsynaptic neuron that
+    /// spiked.
+    pub fn on_post_spikes(&mut self, post_spikes: &[bool], post_voltages: &[V]) {
+        for (post, _) in post_spikes.iter().enumerate().filter(|(_, &s)| s) {
+            for col in self.column_starts[post]..self.column_starts[post + 1] {
+                let idx = self.column_synapses[col];
+                self.catch_up(idx);
+                self.synapses[idx].on_post(post_voltages[post]);
+            }
+        }
+    }
+
+    /// Hands the spikes of a time-step of the neurons to the synapses, with
+    /// `on_pre_spikes` and `on_post_spikes`, and then moves the delays on
+    /// with `tick`. This is what a network does once its neurons are updated.
+    pub fn learn(
+        &mut self,
+        pre_spikes: &[bool],
+        pre_voltages: &[V],
+        post_spikes: &[bool],
+        post_voltages: &[V],
+        dt: T,
+    ) {
+        self.on_pre_spikes(pre_spikes, pre_voltages);
+        self.on_post_spikes(post_spikes, post_voltages);
+        self.tick(dt);
+    }
+
+    /// Advances every synapse and the delays by a time-step, as `tick` and
+    /// `advance_synapses` together.
+    ///
+    /// Panics if the group has delays and the time-step is not the one they
+    /// were set up with.
+    pub fn advance(&mut self, dt: T) {
+        self.tick(dt);
+        self.advance_synapses(dt);
+    }
+
+    /// Moves the spikes and voltages held back by the delays on by a
+    /// time-step of the neurons, without advancing the synapses.
+    ///
+    /// Panics if the group has delays and the time-step is not the one they
+    /// were set up with.
+    pub fn tick(&mut self, dt: T) {
+        assert!(
+            self.delay_dt.is_none_or(|delay_dt| delay_dt == dt),
+            "The delays were set up for another time-step"
+        );
+        self.ticks += 1;
+    }
+
+    /// Advances every synapse by a time-step, without moving the delays on.
+    /// If the time-step differs from the previous one, the group is flushed
+    /// first.
+    pub fn advance_synapses(&mut self, dt: T) {
+        if self.dt.is_some_and(|prev| prev != dt) {
+            self.flush();
+        }
+        self.dt = Some(dt);
+        self.steps += 1;
+    }
+}
+
+/// The `par_` versions of the methods above, described in `parallel`.
+impl<S, V, T> SynapseGroup<S, V, T>
+where
+    S: Synaptic<V, T> + Send,
+    V: From<si::Volt<f64>>
+        + Copy
+        + PartialEq
+        + std::ops::Add<Output = V>
+        + std::ops::Mul<f64, Output = V>,
+    T: Copy + PartialEq + Send + Sync,
+{
+    /// As `propagate`, catching the rows up in parallel.
+    pub fn par_propagate(&mut self, pre_voltages: &[V], post_inputs: &mut [V]) {
+        let zero: V = (0.0 * si::V).into();
+        let (steps, dt) = (self.steps, self.dt);
+        let mut rows = self.rows_mut(|pre| pre_voltages[pre] != zero);
+        parallel::for_each(&mut rows, |(row_synapses, row_updates)| {
+            for (synapse, last) in row_synapses.iter_mut().zip(row_updates.iter_mut()) {
+                catch_up_synapse(synapse, last, steps, dt);
+            }
+        });
+        self.send(pre_voltages, post_inputs);
+    }
+
+    /// As `flush`, catching the synapses up in parallel.
+    pub fn par_flush(&mut self) {
+        let (steps, dt) = (self.steps, self.dt);
+        let last_update =
+            std::mem::replace(&mut self.last_update, vec![steps; self.synapses.len()]);
+        parallel::for_each_zipped(&mut self.synapses, last_update, |synapse, mut last| {
+            catch_up_synapse(synapse, &mut last, steps, dt)
+        });
+    }
+}
@@ -0,0 +1,77 @@
+//! Traits for models that are generic over their units and float type.
+//!
+//! `define_neuron!` and `define_synapse!` accept type parameters in place of
+//! the voltage, time and float types, as in `Lif<V: Quantity<F>, T:
+//! Quantity<F>, F: Float>`. One definition then works with `si::Volt<f64>`,
+//! `si::Volt<f32>` or plain `f64`. The macros add the bounds that their
+//! integrators need themselves, so these traits only need to cover what the
+//! equations of a model use.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A primitive float that a model can be computed in.
+pub trait Float:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// How much of a time-step a countdown, such as a refractory period, may
+    /// have left over from rounding and still be over.
+    const STEP_TOLERANCE: Self;
+
+    /// Converts a constant, rounding it if need be.
+    fn from_f64(value: f64) -> Self;
+
+    /// Converts to `f64`, as for the weight of a synapse.
+    fn to_f64(self) -> f64;
+}
+
+impl Float for f32 {
+    const STEP_TOLERANCE: Self = 1e-3;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    const STEP_TOLERANCE: Self = 1e-9;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// A quantity, such as a voltage or a time, held in floats of type `F`. It
+/// may have units, as with `si::Volt<F>`, or not, as with `F` itself.
+pub trait Quantity<F>:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<F, Output = Self>
+    + Div<F, Output = Self>
+{
+}
+
+impl<F, Q> Quantity<F> for Q where
+    Q: Copy
+        + PartialOrd
+        + Add<Output = Q>
+        + Sub<Output = Q>
+        + Mul<F, Output = Q>
+        + Div<F, Output = Q>
+{
+}
@@ -0,0 +1,13 @@
+//! Checks that mistakes in the DSL of `define_neuron!` and `define_synapse!`
+//! are reported at the right place, and that the models in `tests/pass`
+//! compile with nothing more than they import. Run with `TRYBUILD=overwrite`
+//! to update the expected errors in `tests/ui`.
+
+extern crate trybuild;
+
+#[test]
+fn dsl_diagnostics() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+    cases.pass("tests/pass/*.rs");
+}
@@ -0,0 +1,31 @@
+extern crate brian_rs;
+extern crate brian_rs_macros;
+extern crate dimensioned as dim;
+
+use brian_rs::spike_generators::{InnerSpikeGenerator, SpikeGenerator};
+use dim::si;
+
+// The bounds name `Float` and `Quantity` by their paths, without importing
+// them.
+brian_rs_macros::define_neuron! {
+    GenericLif<
+        V: brian_rs::units::Quantity<F>,
+        T: brian_rs::units::Quantity<F>,
+        F: brian_rs::units::Float
+    >:
+    params { tau: T, v_rest: V, threshold: V }
+    initialize { v: V = v_rest }
+    method { rk4 }
+    time_step { v @ = (input + self.v_rest - self.v) / self.tau (unless refractory) }
+    spike_when { self.v > self.threshold }
+    get_voltage { self.v }
+    reset { self.v = self.v_rest }
+    refractory { self.tau * <F as brian_rs::units::Float>::from_f64(0.5) }
+}
+
+fn main() {
+    let mut neuron: GenericLif<si::Volt<f64>, si::Second<f64>, f64> =
+        GenericLif::new(0.01 * si::S, 0.0 * si::V, 0.5 * si::V);
+    neuron.handle_input(1.0 * si::V, 0.001 * si::S);
+    assert!(neuron.get_voltage() > 0.0 * si::V);
+}
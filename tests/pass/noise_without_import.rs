@@ -0,0 +1,23 @@
+extern crate brian_rs;
+extern crate brian_rs_macros;
+extern crate dimensioned as dim;
+
+use brian_rs::spike_generators::{InnerSpikeGenerator, SpikeGenerator};
+use dim::si;
+
+// `Noise` is only named by its path.
+brian_rs_macros::define_neuron! {
+    NoisyMembrane<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, sigma: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) + self.sigma * xi * si::V / si::S }
+    spike_when { false }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+}
+
+fn main() {
+    let mut neuron = NoisyMembrane::new(0.01, 1.0, brian_rs::noise::Noise::new(7));
+    neuron.handle_input(0.0 * si::V, 0.0001 * si::S);
+    assert!(neuron.get_voltage() != 0.0 * si::V);
+}
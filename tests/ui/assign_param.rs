@@ -0,0 +1,13 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_neuron! {
+    Lif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, threshold: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { tau @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v > self.threshold * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+}
+
+fn main() {}
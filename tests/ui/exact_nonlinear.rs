@@ -0,0 +1,20 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_neuron! {
+    Adaptive<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, tau_w: f64 }
+    initialize {
+        v: si::Volt<f64> = 0.0 * si::V;
+        w: si::Volt<f64> = 0.0 * si::V
+    }
+    method { exact }
+    time_step {
+        v @ = (input - self.v - self.w) / (self.tau * si::S);
+        w @ = self.v * self.w / (self.tau_w * si::S * si::V)
+    }
+    spike_when { self.v > 1.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+}
+
+fn main() {}
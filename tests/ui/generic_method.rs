@@ -0,0 +1,14 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_neuron! {
+    Lif<V: Quantity<F>, T: Quantity<F>, F: Float>:
+    params { tau: T, threshold: V }
+    initialize { v: V = threshold }
+    method { exact }
+    time_step { v @ = (input - self.v) / self.tau }
+    spike_when { self.v > self.threshold }
+    get_voltage { self.v }
+    reset { self.v = self.threshold }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_network! {
+    Net<si::Volt<f64>, si::Second<f64>>:
+    inputs { drive: Pacemaker[2](0.008, 2.0) }
+    populations { hidden: Lif[2](0.01, 1.0) }
+    projections { back: Stdp[hidden -> drive](1.0) }
+}
+
+fn main() {}
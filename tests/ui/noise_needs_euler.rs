@@ -0,0 +1,14 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_neuron! {
+    NoisyLif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, sigma: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    method { rk4 }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) + self.sigma * xi * si::V / si::S }
+    spike_when { self.v > 1.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+}
+
+fn main() {}
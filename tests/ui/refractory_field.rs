@@ -0,0 +1,14 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_neuron! {
+    Lif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V; refractory_left: f64 = 0.0 }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) (unless refractory) }
+    spike_when { self.v > 1.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+    refractory { 0.005 * si::S }
+}
+
+fn main() {}
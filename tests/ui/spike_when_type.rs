@@ -0,0 +1,18 @@
+extern crate brian_rs;
+extern crate brian_rs_macros;
+extern crate dimensioned as dim;
+
+use brian_rs::spike_generators::{InnerSpikeGenerator, SpikeGenerator};
+use dim::si;
+
+brian_rs_macros::define_neuron! {
+    Lif<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64 }
+    initialize { v: si::Volt<f64> = 0.0 * si::V }
+    time_step { v @ = (input - self.v) / (self.tau * si::S) }
+    spike_when { self.v - 1.0 * si::V }
+    get_voltage { self.v }
+    reset { self.v = 0.0 * si::V }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+extern crate brian_rs_macros;
+
+brian_rs_macros::define_synapse! {
+    Stdp<si::Volt<f64>, si::Second<f64>>:
+    params { tau: f64, w: si::Unitless<f64> }
+    initialize { a: si::Unitless<f64> = 0.0 * si::S / si::S }
+    time_step { w @ = - (self.a / self.tau) / si::S }
+    weight_getter { *self.w }
+    on_pre { self.a += 1.0 }
+    on_post { self.w += self.a }
+}
+
+fn main() {}